@@ -1,73 +1,268 @@
-use crate::{compile::CompiledEntries, error, profile::AttrType};
-use std::path::Path;
-use thisctx::WithContext;
+use crate::{
+    compile::{CompiledEntries, CompiledProfile},
+    error,
+    plan::{plan_entry, Action},
+    profile::AttrType,
+    status::Fingerprint,
+    transaction::{unused_path, Transaction},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+use thisctx::{IntoError, WithContext};
+use time::OffsetDateTime;
+
+/// The template file being rendered and where it's rendered into.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderContext<'a> {
+    pub target: &'a Path,
+    pub source: &'a Path,
+    pub profile: &'a CompiledProfile,
+}
+
+impl<'a> RenderContext<'a> {
+    pub fn new(target: &'a Path, profile: &'a CompiledProfile) -> Self {
+        RenderContext {
+            target,
+            source: &profile.source,
+            profile,
+        }
+    }
+}
 
 pub trait TemplateRenderer {
     type Err;
 
-    fn render(&mut self, s: &str) -> Result<String, Self::Err>;
+    /// Renders the content `s` of the template described by `context`.
+    fn render(&mut self, s: &str, context: &RenderContext) -> Result<String, Self::Err>;
 }
 
-fn create_symlink(original: &Path, link: &Path) -> error::Result<()> {
-    #[cfg(unix)]
-    {
-        std::os::unix::fs::symlink(original, link).context(error::IoFailed(original))?;
+pub(crate) fn render_template<R: TemplateRenderer>(
+    renderer: &mut R,
+    target: &Path,
+    profile: &CompiledProfile,
+) -> error::Result<String>
+where
+    R::Err: 'static + std::error::Error + Send + Sync,
+{
+    let context = RenderContext::new(target, profile);
+    let source = context.source;
+    let s = std::fs::read_to_string(source).context(error::IoFailed(source))?;
+    renderer
+        .render(&s, &context)
+        .map_err(|e| Box::new(e) as Box<_>)
+        .context(error::RenderError(source, target))
+}
+
+/// Returns the contents expected at `target` by a copied or rendered
+/// `profile`.
+pub fn expected_contents<R: TemplateRenderer>(
+    renderer: &mut R,
+    target: &Path,
+    profile: &CompiledProfile,
+) -> error::Result<Vec<u8>>
+where
+    R::Err: 'static + std::error::Error + Send + Sync,
+{
+    match profile.ty {
+        AttrType::Template => Ok(render_template(renderer, target, profile)?.into_bytes()),
+        _ => {
+            let source = &profile.source;
+            std::fs::read(source).context(error::IoFailed(source))
+        }
     }
-    #[cfg(not(unix))]
-    {
-        unreachable!("symlinks are only supported on unix systems")
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Fail on the first conflicting target.
+    #[default]
+    Error,
+    /// Remove conflicting targets.
+    Overwrite,
+    /// Leave conflicting targets untouched.
+    Skip,
+    /// Move conflicting targets into a timestamped backup directory.
+    Backup,
+    /// Rename conflicting targets by appending a suffix.
+    Rename,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "error" => ConflictPolicy::Error,
+            "overwrite" => ConflictPolicy::Overwrite,
+            "skip" => ConflictPolicy::Skip,
+            "backup" => ConflictPolicy::Backup,
+            "rename" => ConflictPolicy::Rename,
+            _ => return Err(format!("unknown conflict policy '{s}'")),
+        })
     }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ApplyOptions<'a> {
+    pub conflict: ConflictPolicy,
+    /// Where to store backups when the policy is [`ConflictPolicy::Backup`].
+    pub backup_dir: &'a Path,
+    /// Suffix to append when the policy is [`ConflictPolicy::Rename`].
+    pub rename_suffix: &'a str,
+}
+
+/// Conflicts resolved while applying.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Applied {
+    /// Targets left untouched, which should not be treated as managed.
+    pub skipped: Vec<PathBuf>,
+    /// Conflicting targets and where they have been moved to.
+    pub backups: BTreeMap<PathBuf, PathBuf>,
+    /// States of all applied targets.
+    pub fingerprints: BTreeMap<PathBuf, Fingerprint>,
+}
+
+fn backup_timestamp() -> String {
+    let now = OffsetDateTime::now_utc();
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+    )
+}
 
+#[cfg(unix)]
+fn set_permissions(
+    transaction: &mut Transaction,
+    target: &Path,
+    profile: &CompiledProfile,
+) -> error::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode_of = |path: &Path| -> error::Result<u32> {
+        let metadata = path.metadata().context(error::IoFailed(path))?;
+        Ok(metadata.permissions().mode() & 0o7777)
+    };
+    let permissions = &profile.permissions;
+    let current = mode_of(target)?;
+    let base = if permissions
+        .preserve_mode
+        .unwrap_or(profile.ty == AttrType::Copy)
+    {
+        mode_of(&profile.source)?
+    } else {
+        current
+    };
+    let mode = permissions.resolve(base);
+    if mode != current {
+        transaction.set_mode(target, mode)?;
+    }
     Ok(())
 }
 
-pub fn apply<R: TemplateRenderer>(renderer: &mut R, entries: &CompiledEntries) -> error::Result<()>
+/// Applies `entries` to their targets. If any entry fails, all changes are
+/// rolled back before returning the error.
+pub fn apply<R: TemplateRenderer>(
+    renderer: &mut R,
+    entries: &CompiledEntries,
+    options: &ApplyOptions,
+) -> error::Result<Applied>
+where
+    R::Err: 'static + std::error::Error + Send + Sync,
+{
+    Transaction::run(|transaction| apply_in(transaction, renderer, entries, options))
+}
+
+/// Applies `entries` to their targets and records all changes in
+/// `transaction`, which the caller is responsible to commit or roll back.
+pub fn apply_in<R: TemplateRenderer>(
+    transaction: &mut Transaction,
+    renderer: &mut R,
+    entries: &CompiledEntries,
+    options: &ApplyOptions,
+) -> error::Result<Applied>
 where
     R::Err: 'static + std::error::Error + Send + Sync,
 {
+    let backup_dir = options.backup_dir.join(backup_timestamp());
+    let mut applied = Applied::default();
     for (target, profile) in entries.0.iter() {
-        if let Some(dir) = target.parent() {
-            std::fs::create_dir_all(dir).context(error::IoFailed(dir))?;
-        }
-        match profile.ty {
-            AttrType::Template => {
-                let path = &profile.source;
-                let s = std::fs::read_to_string(path).context(error::IoFailed(path))?;
-                let content = renderer
-                    .render(&s)
-                    .map_err(|e| Box::new(e) as Box<_>)
-                    .context(error::RenderError(path))?;
-                std::fs::write(target, content).context(error::IoFailed(path))?;
-            }
-            AttrType::Copy => {
-                std::fs::copy(&profile.source, target).context(error::IoFailed(&profile.source))?;
+        // Resolve conflicts with existing targets.
+        let changed = match plan_entry(renderer, target, profile)? {
+            Action::Create => true,
+            Action::Unchanged => false,
+            _ => match options.conflict {
+                ConflictPolicy::Error => return error::Conflict(target).fail(),
+                ConflictPolicy::Overwrite => {
+                    transaction.remove(target)?;
+                    true
+                }
+                ConflictPolicy::Skip => {
+                    applied.skipped.push(target.clone());
+                    continue;
+                }
+                ConflictPolicy::Backup => {
+                    let backup = unused_path(
+                        backup_dir.join(
+                            target
+                                .components()
+                                .filter(|compo| matches!(compo, Component::Normal(_)))
+                                .collect::<PathBuf>(),
+                        ),
+                    );
+                    transaction.rename(target, &backup)?;
+                    applied.backups.insert(target.clone(), backup);
+                    true
+                }
+                ConflictPolicy::Rename => {
+                    let mut renamed = target.clone().into_os_string();
+                    renamed.push(options.rename_suffix);
+                    let renamed = unused_path(PathBuf::from(renamed));
+                    transaction.rename(target, &renamed)?;
+                    applied.backups.insert(target.clone(), renamed);
+                    true
+                }
+            },
+        };
+
+        if changed {
+            match profile.ty {
+                AttrType::Copy if profile.permissions.preserve_mode != Some(false) => {
+                    transaction.copy(&profile.source, target)?
+                }
+                AttrType::Template | AttrType::Copy => {
+                    let content = expected_contents(renderer, target, profile)?;
+                    transaction.write(target, content)?;
+                }
+                AttrType::Link => transaction.symlink(&profile.link_original(target), target)?,
             }
-            AttrType::Link => create_symlink(&profile.source, target)?,
+        }
+        #[cfg(unix)]
+        if profile.ty != AttrType::Link {
+            set_permissions(transaction, target, profile)?;
+        }
+        if let Some(fingerprint) = Fingerprint::of(target)? {
+            applied.fingerprints.insert(target.clone(), fingerprint);
         }
     }
-    Ok(())
+    Ok(applied)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{compile, compile::CompilerOptions, Profile};
+    use crate::utils::{apply_options, compile_profile, EmptyRenderer};
     use std::path::Path;
-    use thiserror::Error;
-
-    struct EmptyRenderer;
-
-    #[derive(Debug, Error)]
-    #[error("")]
-    struct RendererErr;
-
-    impl TemplateRenderer for EmptyRenderer {
-        type Err = RendererErr;
-
-        fn render(&mut self, s: &str) -> Result<String, Self::Err> {
-            Ok(s.to_owned())
-        }
-    }
+    use tempfile::TempDir;
 
     fn create_tmp_tree(tmp: &Path) {
         create_tree!(tmp, {
@@ -83,15 +278,53 @@ mod tests {
     }
 
     fn apply_profile(profile: &str, source: &Path, target: &Path) {
-        let entries = compile(
-            &CompilerOptions { source, target },
-            serde_yaml::from_str::<Profile>(profile)
-                .unwrap()
-                .into_entries()
-                .unwrap(),
-        )
-        .unwrap();
-        apply(&mut EmptyRenderer, &entries).unwrap();
+        let entries = compile_profile(profile, source, target);
+        let options = apply_options(ConflictPolicy::Error, "".as_ref());
+        apply(&mut EmptyRenderer, &entries, &options).unwrap();
+    }
+
+    struct Conflicting {
+        target: TempDir,
+        backup: TempDir,
+        result: error::Result<Applied>,
+    }
+
+    impl Conflicting {
+        fn read(&self, path: &str) -> String {
+            std::fs::read_to_string(self.target.path().join(path)).unwrap()
+        }
+    }
+
+    fn apply_conflicting(conflict: ConflictPolicy) -> Conflicting {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let backup = tempfile::tempdir().unwrap();
+        create_tmp_tree(source.path());
+        create_tree!(target.path(), {
+            path: {
+                to: {
+                    target: {
+                        file2,
+                    },
+                },
+            },
+        });
+        std::fs::write(source.path().join("path/to/source/file1"), "source").unwrap();
+        std::fs::write(target.path().join("path/to/target/file1"), "target").unwrap();
+        let entries = compile_profile(
+            r#"
+            path/to/target: path/to/source
+            "#,
+            source.path(),
+            target.path(),
+        );
+        let options = apply_options(conflict, backup.path());
+        let result = apply(&mut EmptyRenderer, &entries, &options);
+        Conflicting {
+            target,
+            backup,
+            result,
+        }
     }
 
     #[test]
@@ -126,9 +359,9 @@ mod tests {
         apply_profile(
             r#"
             path/to/target:
-              ~source: path/to/source
-              ~type: template
-              ~recursive: true
+              +source: path/to/source
+              +type: template
+              +recursive: true
             "#,
             source.path(),
             target.path(),
@@ -154,9 +387,9 @@ mod tests {
         apply_profile(
             r#"
             path/to/target:
-              ~source: path/to/source
-              ~type: link
-              ~recursive: true
+              +source: path/to/source
+              +type: link
+              +recursive: true
             "#,
             source.path(),
             target.path(),
@@ -173,6 +406,31 @@ mod tests {
         });
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn apply_relative_link() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        create_tmp_tree(&source);
+        apply_profile(
+            r#"
+            path/to/target:
+              +source: path/to/source
+              +type: link
+              +relative: true
+            "#,
+            &source,
+            &target,
+        );
+        let link = target.join("path/to/target");
+        assert_eq!(
+            std::fs::read_link(&link).unwrap(),
+            Path::new("../../../source/path/to/source")
+        );
+        assert!(link.join("file1").is_file());
+    }
+
     #[cfg(unix)]
     #[test]
     fn apply_link_dir() {
@@ -182,8 +440,8 @@ mod tests {
         apply_profile(
             r#"
             path/to/target:
-              ~source: path/to/source
-              ~type: link
+              +source: path/to/source
+              +type: link
             "#,
             source.path(),
             target.path(),
@@ -196,4 +454,202 @@ mod tests {
             },
         });
     }
+
+    #[test]
+    fn conflict_error() {
+        let conflicting = apply_conflicting(ConflictPolicy::Error);
+        let path = conflicting.target.path().join("path/to/target/file1");
+        assert!(
+            matches!(&conflicting.result, Err(error::Error::Conflict(p)) if p == &path),
+            "{:?}",
+            conflicting.result
+        );
+        assert_eq!(conflicting.read("path/to/target/file1"), "target");
+    }
+
+    #[test]
+    fn conflict_overwrite() {
+        let conflicting = apply_conflicting(ConflictPolicy::Overwrite);
+        assert!(conflicting.result.as_ref().unwrap().backups.is_empty());
+        assert_eq!(conflicting.read("path/to/target/file1"), "source");
+    }
+
+    #[test]
+    fn conflict_skip() {
+        let conflicting = apply_conflicting(ConflictPolicy::Skip);
+        let path = conflicting.target.path().join("path/to/target/file1");
+        assert_eq!(conflicting.result.as_ref().unwrap().skipped, vec![path]);
+        assert_eq!(conflicting.read("path/to/target/file1"), "target");
+    }
+
+    #[test]
+    fn conflict_backup() {
+        let conflicting = apply_conflicting(ConflictPolicy::Backup);
+        let path = conflicting.target.path().join("path/to/target/file1");
+        let backups = &conflicting.result.as_ref().unwrap().backups;
+        assert_eq!(backups.len(), 1);
+        let backup = &backups[&path];
+        assert!(backup.starts_with(conflicting.backup.path()));
+        assert!(backup.ends_with("path/to/target/file1"));
+        assert_eq!(std::fs::read_to_string(backup).unwrap(), "target");
+        assert_eq!(conflicting.read("path/to/target/file1"), "source");
+    }
+
+    #[test]
+    fn conflict_rename() {
+        let conflicting = apply_conflicting(ConflictPolicy::Rename);
+        let path = conflicting.target.path().join("path/to/target/file1");
+        let backups = &conflicting.result.as_ref().unwrap().backups;
+        assert_eq!(backups[&path], path.with_file_name("file1.bak"));
+        assert_eq!(conflicting.read("path/to/target/file1.bak"), "target");
+        assert_eq!(conflicting.read("path/to/target/file1"), "source");
+    }
+
+    #[test]
+    fn conflict_rename_existing() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        create_tmp_tree(source.path());
+        apply_profile("target: path/to/source", source.path(), target.path());
+        let path = target.path().join("target/file1");
+        std::fs::write(&path, "modified").unwrap();
+        std::fs::write(path.with_file_name("file1.bak"), "precious").unwrap();
+        let entries = compile_profile("target: path/to/source", source.path(), target.path());
+        let options = apply_options(ConflictPolicy::Rename, "".as_ref());
+        let applied = apply(&mut EmptyRenderer, &entries, &options).unwrap();
+        let renamed = path.with_file_name("file1.bak.1");
+        assert_eq!(applied.backups[&path], renamed);
+        assert_eq!(std::fs::read_to_string(&renamed).unwrap(), "modified");
+        assert_eq!(
+            std::fs::read_to_string(path.with_file_name("file1.bak")).unwrap(),
+            "precious"
+        );
+    }
+
+    #[test]
+    fn conflict_backup_existing() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        create_tmp_tree(source.path());
+        apply_profile("target: path/to/source", source.path(), target.path());
+        let path = target.path().join("target/file1");
+        let entries = compile_profile("target: path/to/source", source.path(), target.path());
+        let options = apply_options(ConflictPolicy::Backup, backup_dir.path());
+        // Applies twice within the same second to share the timestamp.
+        let mut backups = Vec::new();
+        for content in ["first", "second"] {
+            std::fs::write(&path, content).unwrap();
+            let applied = apply(&mut EmptyRenderer, &entries, &options).unwrap();
+            backups.push(applied.backups[&path].clone());
+        }
+        assert_ne!(backups[0], backups[1]);
+        assert_eq!(std::fs::read_to_string(&backups[0]).unwrap(), "first");
+        assert_eq!(std::fs::read_to_string(&backups[1]).unwrap(), "second");
+    }
+
+    struct FailingRenderer;
+
+    impl TemplateRenderer for FailingRenderer {
+        type Err = crate::utils::RendererErr;
+
+        fn render(&mut self, s: &str, _: &RenderContext) -> Result<String, Self::Err> {
+            match s {
+                "fail" => Err(crate::utils::RendererErr),
+                _ => Ok(s.to_owned()),
+            }
+        }
+    }
+
+    #[test]
+    fn rollback_on_failure() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        create_tmp_tree(source.path());
+        create_tree!(target.path(), {
+            path: {
+                to: {
+                    target: {
+                        file1,
+                    },
+                },
+            },
+        });
+        std::fs::write(source.path().join("path/to/source/file2"), "fail").unwrap();
+        std::fs::write(target.path().join("path/to/target/file1"), "target").unwrap();
+        let entries = compile_profile(
+            r#"
+            path/to/target:
+              +source: path/to/source
+              +type: template
+              +recursive: true
+            path/to/new: path/to/source/file1
+            "#,
+            source.path(),
+            target.path(),
+        );
+        let options = apply_options(ConflictPolicy::Overwrite, "".as_ref());
+        let result = apply(&mut FailingRenderer, &entries, &options);
+        assert!(
+            matches!(
+                &result,
+                Err(error::Error::RenderError(_, source, target))
+                    if source.ends_with("path/to/source/file2")
+                        && target.ends_with("path/to/target/file2")
+            ),
+            "{result:?}"
+        );
+        let dir = target.path().join("path/to/target");
+        let files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["file1"]);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("file1")).unwrap(),
+            "target"
+        );
+        assert!(!target.path().join("path/to/new").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn apply_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        create_tmp_tree(source.path());
+        let set_mode = |path: &str, mode: u32| {
+            let path = source.path().join(path);
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+        };
+        let mode = |path: &str| {
+            let metadata = target.path().join(path).metadata().unwrap();
+            metadata.permissions().mode() & 0o7777
+        };
+        set_mode("path/to/source/file1", 0o755);
+        set_mode("path/to/source/file2", 0o644);
+        apply_profile(
+            r#"
+            file1: path/to/source/file1
+            file2:
+              +source: path/to/source/file2
+              +mode: "0600"
+            file3:
+              +source: path/to/source/file2
+              +type: template
+              +executable: true
+            file4:
+              +source: path/to/source/file1
+              +private: true
+            "#,
+            source.path(),
+            target.path(),
+        );
+        assert_eq!(mode("file1"), 0o755);
+        assert_eq!(mode("file2"), 0o600);
+        assert_eq!(mode("file3") & 0o111, 0o111);
+        assert_eq!(mode("file4"), 0o700);
+    }
 }
@@ -0,0 +1,324 @@
+use crate::{error, Runtime, F_PROFILE};
+use dbot::{
+    compile::CompiledEntries,
+    profile::{normalize_path, AttrType},
+    Transaction,
+};
+use std::path::{Component, Path, PathBuf};
+use thisctx::{IntoError, WithContext};
+use tracing::info;
+
+const DEFAULT_INDENT: &str = "  ";
+
+impl Runtime {
+    pub fn add(&mut self, path: &Path, ty: AttrType, replace: bool) -> error::Result<()> {
+        let options = self.options()?;
+        let source_dir = options.source();
+        let target_dir = options.target();
+
+        // 1) Resolve the target path relative to the target directory.
+        let (original, target) = resolve_target(target_dir, path)?;
+        let metadata = original.symlink_metadata().context(error::Io(&original))?;
+
+        // 2) Check whether the target is already managed.
+        let profile = self.load_profile(source_dir)?;
+        if let Some(profile) = profile.content.profile {
+            for (managed, _) in profile.into_entries()?.iter() {
+                if target.starts_with(managed) || managed.starts_with(&target) {
+                    return error::AlreadyManaged(&original).fail();
+                }
+            }
+        }
+
+        // 3) Copy into the source directory and insert a node into the
+        // profile, which are rolled back if the new profile is invalid.
+        let source = source_path(&target);
+        let source_full = source_dir.join(&source);
+        if source_full.symlink_metadata().is_ok() {
+            return error::SourceExists(&source_full).fail();
+        }
+        let profile_path = source_dir.join(F_PROFILE);
+        let content = std::fs::read_to_string(&profile_path).context(error::Io(&profile_path))?;
+        let recursive = ty == AttrType::Template && metadata.is_dir();
+        let content = insert_profile_node(&content, &target, &source, ty, recursive)
+            .context(error::UneditableProfile(&profile_path))?;
+        let added = Transaction::run(|transaction| -> error::Result<_> {
+            copy_all(transaction, &original, &source_full)?;
+            transaction.remove(&profile_path)?;
+            transaction.write(&profile_path, &content)?;
+            let (mut renderer, entries) = self.compile()?;
+            info!(
+                "Add '{}' as '{}'",
+                original.display(),
+                source_full.display()
+            );
+
+            // 4) Replace the original with what applying the new node
+            // creates, which is then managed as if it had been applied.
+            if !replace {
+                return Ok(None);
+            }
+            let mut added = CompiledEntries::default();
+            for (path, profile) in entries.iter() {
+                if path.starts_with(target_dir.join(&target)) {
+                    added.insert(path.to_owned(), profile.clone());
+                }
+            }
+            transaction.remove(&original)?;
+            let applied =
+                dbot::apply_in(transaction, &mut renderer, &added, &options.apply_options())?;
+            Ok(Some((added, applied.fingerprints)))
+        })?;
+        if let Some((added, fingerprints)) = added {
+            let history = self.history_mut()?;
+            match history.last_mut() {
+                Some(last) => {
+                    for (target, profile) in added.iter() {
+                        last.entries.insert(target.to_owned(), profile.clone());
+                    }
+                    last.fingerprints.extend(fingerprints);
+                }
+                None => history.push(added, Default::default(), fingerprints),
+            }
+            self.save_histroy()?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the absolute path of `path` and the path relative to `target_dir`.
+pub fn resolve_target(target_dir: &Path, path: &Path) -> error::Result<(PathBuf, PathBuf)> {
+    let original = if path.is_absolute() {
+        path.to_owned()
+    } else {
+        std::env::current_dir().context(error::Io(path))?.join(path)
+    };
+    let target = original
+        .strip_prefix(target_dir)
+        .ok()
+        .map(normalize_path)
+        .transpose()?
+        .filter(|target| !target.as_os_str().is_empty())
+        .context(error::NotUnderTarget(&original))?;
+    Ok((original, target))
+}
+
+/// Returns where to put `target` in the source directory, where the leading
+/// dot of hidden files is removed, e.g. `.config/nvim` becomes `config/nvim`.
+fn source_path(target: &Path) -> PathBuf {
+    let mut components = target.components();
+    let mut source = PathBuf::new();
+    if let Some(Component::Normal(first)) = components.next() {
+        let first = first.to_string_lossy();
+        source.push(first.strip_prefix('.').unwrap_or(&first));
+    }
+    source.extend(components);
+    source
+}
+
+fn copy_all(transaction: &mut Transaction, from: &Path, to: &Path) -> error::Result<()> {
+    if from.is_dir() {
+        transaction.create_dir_all(to)?;
+        for entry in std::fs::read_dir(from).context(error::Io(from))? {
+            let entry = entry.context(error::Io(from))?;
+            copy_all(transaction, &entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        transaction.copy(from, to)?;
+    }
+    Ok(())
+}
+
+fn yaml_str(path: &Path) -> String {
+    let s = path.to_string_lossy();
+    serde_yaml::to_string(&s)
+        .map(|s| s.trim_end().to_owned())
+        .unwrap_or_else(|_| s.into_owned())
+}
+
+/// Appends a node to the top-level `profile` mapping without touching the
+/// rest of the document, returns `None` if the mapping is not in block style.
+fn insert_profile_node(
+    content: &str,
+    target: &Path,
+    source: &Path,
+    ty: AttrType,
+    recursive: bool,
+) -> Option<String> {
+    let lines = content.lines().collect::<Vec<_>>();
+    let is_top_level = |line: &&str| !line.is_empty() && !line.starts_with([' ', '\t', '#']);
+    let profile = lines.iter().position(|line| {
+        line.strip_prefix("profile:")
+            .map(|rest| rest.trim().is_empty() || rest.trim_start().starts_with('#'))
+            .unwrap_or_default()
+    });
+    if profile.is_none() && lines.iter().any(|line| line.starts_with("profile:")) {
+        return None;
+    }
+
+    // Find the last line and the indentation of the mapping.
+    let (insert_at, indent) = match profile {
+        Some(i) => {
+            let children = lines[i + 1..]
+                .iter()
+                .take_while(|line| !is_top_level(line))
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty() && !line.trim().starts_with('#'))
+                .collect::<Vec<_>>();
+            let indent = children
+                .first()
+                .map(|(_, line)| &line[..line.len() - line.trim_start().len()])
+                .unwrap_or(DEFAULT_INDENT);
+            let last = children.last().map(|(j, _)| i + 1 + j).unwrap_or(i);
+            (last + 1, indent.to_owned())
+        }
+        None => (lines.len(), DEFAULT_INDENT.to_owned()),
+    };
+
+    let mut node = Vec::new();
+    if profile.is_none() {
+        node.push("profile:".to_owned());
+    }
+    if ty == AttrType::Copy {
+        node.push(format!(
+            "{indent}{}: {}",
+            yaml_str(target),
+            yaml_str(source)
+        ));
+    } else {
+        node.push(format!("{indent}{}:", yaml_str(target)));
+        node.push(format!("{indent}{indent}+source: {}", yaml_str(source)));
+        node.push(format!("{indent}{indent}+type: {ty}"));
+        if recursive {
+            node.push(format!("{indent}{indent}+recursive: true"));
+        }
+    }
+
+    let mut new = lines[..insert_at]
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+    new.extend(node);
+    new.extend(lines[insert_at..].iter().map(|line| line.to_string()));
+    let mut new = new.join("\n");
+    new.push('\n');
+    Some(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_source_path() {
+        assert_eq!(
+            source_path(".config/nvim".as_ref()),
+            Path::new("config/nvim")
+        );
+        assert_eq!(source_path("bin/script".as_ref()), Path::new("bin/script"));
+    }
+
+    fn add_replaced(ty: AttrType) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let original = dir.path().join("home/.config/foo.toml");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(original.parent().unwrap()).unwrap();
+        std::fs::write(source.join(F_PROFILE), "profile:\n  .bashrc: bashrc\n").unwrap();
+        std::fs::write(source.join("bashrc"), "").unwrap();
+        std::fs::write(&original, "key = 1").unwrap();
+        let mut rt = Runtime::new_in(dir.path());
+        rt.add(&original, ty, true).unwrap();
+        let last = rt.history().unwrap().last().unwrap();
+        assert!(last.entries.get(&original).is_some());
+        assert!(last.fingerprints.contains_key(&original));
+        (dir, original)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replace_with_link() {
+        let (dir, original) = add_replaced(AttrType::Link);
+        assert_eq!(
+            std::fs::read_link(&original).unwrap(),
+            dir.path().join("source/config/foo.toml")
+        );
+    }
+
+    #[test]
+    fn replace_with_copy() {
+        let (_dir, original) = add_replaced(AttrType::Copy);
+        assert!(!original.symlink_metadata().unwrap().is_symlink());
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "key = 1");
+    }
+
+    #[test]
+    fn copy_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("from");
+        std::fs::create_dir_all(from.join("nested")).unwrap();
+        std::fs::write(from.join("nested/file"), "content").unwrap();
+        let to = dir.path().join("source/to");
+        let mut transaction = Transaction::default();
+        copy_all(&mut transaction, &from, &to).unwrap();
+        assert!(to.join("nested/file").is_file());
+        transaction.rollback().unwrap();
+        assert!(!dir.path().join("source").exists());
+    }
+
+    #[test]
+    fn insert_into_profile() {
+        let content = r#"import: []
+# Profile
+profile:
+    # Shell
+    .bashrc: bashrc
+
+data:
+  key: value
+"#;
+        let expected = r#"import: []
+# Profile
+profile:
+    # Shell
+    .bashrc: bashrc
+    .config/nvim:
+        +source: config/nvim
+        +type: link
+
+data:
+  key: value
+"#;
+        let new = insert_profile_node(
+            content,
+            ".config/nvim".as_ref(),
+            "config/nvim".as_ref(),
+            AttrType::Link,
+            false,
+        );
+        assert_eq!(new.as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn insert_without_profile() {
+        let new = insert_profile_node(
+            "import: []\n",
+            ".bashrc".as_ref(),
+            "bashrc".as_ref(),
+            AttrType::Copy,
+            false,
+        );
+        assert_eq!(
+            new.as_deref(),
+            Some("import: []\nprofile:\n  .bashrc: bashrc\n")
+        );
+        let new = insert_profile_node(
+            "profile: {}\n",
+            ".bashrc".as_ref(),
+            "bashrc".as_ref(),
+            AttrType::Copy,
+            false,
+        );
+        assert_eq!(new, None);
+    }
+}
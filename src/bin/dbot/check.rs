@@ -0,0 +1,29 @@
+use crate::{
+    diagnostic::{error_chain, Diagnostic},
+    error, Runtime,
+};
+use dbot::{error::Diagnostics, profile::Host};
+use thisctx::IntoError;
+
+impl Runtime {
+    pub fn check(&self) -> error::Result<()> {
+        let options = self.options()?;
+        let source = options.source();
+        let mut diagnostics = Diagnostics::default();
+        let profile = self.load_profile_checked(source, &mut diagnostics)?;
+        if let Some(profile) = profile.content.profile {
+            let entries = profile.collect_entries(Some(&Host::current()), &mut diagnostics);
+            dbot::compile::compile_all(&options.compiler_options(), entries, &mut diagnostics);
+        }
+        if diagnostics.is_empty() {
+            return Ok(());
+        }
+        for err in diagnostics.iter() {
+            match Diagnostic::from_dbot(err, source) {
+                Some(diagnostic) => eprint!("{diagnostic}"),
+                None => eprintln!("error: {}", error_chain(err)),
+            }
+        }
+        error::CheckFailed(diagnostics.len()).fail()
+    }
+}
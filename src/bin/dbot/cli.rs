@@ -1,5 +1,7 @@
 use crate::options::Options;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use dbot::profile::AttrType;
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
@@ -12,10 +14,67 @@ pub struct Cli {
 
 #[derive(Debug, Subcommand)]
 pub enum Command {
+    /// Add an existing file into the source directory and profile.
+    Add {
+        /// File or directory to add.
+        path: PathBuf,
+        /// Type of the added target: copy, link or template.
+        #[arg(long = "type", value_name("TYPE"), default_value_t = AttrType::Copy)]
+        ty: AttrType,
+        /// Replace the original file with the target created by applying the
+        /// added node, i.e. a symlink to the added source for `link` and a
+        /// copy or rendered file otherwise.
+        #[arg(long)]
+        replace: bool,
+    },
     /// Clean and create target files.
-    Apply {},
+    Apply {
+        /// Print what would be changed without writing anything.
+        #[arg(long)]
+        dry_run: bool,
+        /// Discard changes made to target files since last applied.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print what would be changed by `apply`.
+    Plan {},
+    /// Show differences between sources and current target files.
+    Diff {},
     /// Remove all target files created last time.
-    Clean {},
+    Clean {
+        /// Discard changes made to target files since last applied.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Copy changes made to copied target files back into their sources.
+    ReAdd {
+        /// Re-add all changed target files without asking.
+        #[arg(long)]
+        all: bool,
+    },
+    /// Show whether managed target files have changed since last applied.
+    Status {},
     /// List all managed target files.
     Ls {},
+    /// Check the profile and report all problems found.
+    Check {},
+    /// Print all variables available to templates.
+    Data {
+        /// Output format: yaml or json.
+        #[arg(long, value_enum, default_value_t = DataFormat::Yaml)]
+        format: DataFormat,
+    },
+    /// Print the JSON Schema of profile files.
+    Schema {},
+    /// Show which profile entries and attributes produce a target.
+    Explain {
+        /// Target file to explain.
+        path: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum DataFormat {
+    Yaml,
+    Json,
 }
@@ -0,0 +1,18 @@
+use crate::{cli::DataFormat, error, Runtime};
+use thisctx::WithContext;
+
+impl Runtime {
+    /// Prints all variables available to templates, which helps to debug them.
+    pub fn data(&self, format: DataFormat) -> error::Result<()> {
+        let profile = self.load_profile(self.options()?.source())?;
+        let context = self.renderer(&profile)?.context().clone().into_json();
+        let content = match format {
+            DataFormat::Yaml => serde_yaml::to_string(&context).context(error::Format)?,
+            DataFormat::Json => {
+                serde_json::to_string_pretty(&context).context(error::Format)? + "\n"
+            }
+        };
+        print!("{content}");
+        Ok(())
+    }
+}
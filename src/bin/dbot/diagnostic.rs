@@ -0,0 +1,190 @@
+use crate::error;
+use dbot::profile::ATTRIBUTES;
+use std::{
+    borrow::Cow,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// An error located in a profile file, rendered as a code frame.
+pub struct Diagnostic {
+    message: String,
+    path: PathBuf,
+    line: usize,
+    column: usize,
+    hint: Option<Cow<'static, str>>,
+}
+
+impl Diagnostic {
+    /// Returns `None` if `err` is not located in any profile file under the
+    /// `source` directory.
+    pub fn new(err: &error::Error, source: &Path) -> Option<Self> {
+        match err {
+            error::Error::Yaml(e, path) => {
+                let location = e.location()?;
+                let (line, column) = (location.line(), location.column());
+                let message = e.to_string();
+                // The location is shown in the code frame instead.
+                let position = format!(" at line {line} column {column}");
+                Some(Diagnostic {
+                    message: message.replacen(&position, "", 1),
+                    path: path.to_owned(),
+                    line,
+                    column,
+                    hint: None,
+                })
+            }
+            error::Error::Dbot(e) => Self::from_dbot(e, source),
+            error::Error::ImportCycle(_, Some(span))
+            | error::Error::InvalidImport(_, _, Some(span))
+            | error::Error::BadImport(_, _, Some(span)) => Some(Diagnostic {
+                message: error_chain(err),
+                path: source.join(&span.file),
+                line: span.location.line,
+                column: span.location.column,
+                hint: match err {
+                    error::Error::ImportCycle(..) => None,
+                    _ => Some("imported paths are relative to the importing file".into()),
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn from_dbot(err: &dbot::Error, source: &Path) -> Option<Self> {
+        let span = err.span()?;
+        Some(Diagnostic {
+            message: err.to_string(),
+            path: source.join(&span.file),
+            line: span.location.line,
+            column: span.location.column,
+            hint: match err {
+                dbot::Error::UnexpectedChildren(..) => {
+                    Some("add `+recursive: true` to deploy children one by one".into())
+                }
+                dbot::Error::InvalidPatternSet(..) => {
+                    Some("patterns in `+ignore` must be valid globs".into())
+                }
+                dbot::Error::MissingSource(..) => {
+                    Some("paths in `+source` are relative to the source directory".into())
+                }
+                dbot::Error::UnexpectedDirectoryForTemplate(..) => {
+                    Some("add `+recursive: true` to render files in the directory".into())
+                }
+                dbot::Error::UnknownAttribute(..) => {
+                    Some(format!("attributes are {}", ATTRIBUTES.join(", ")).into())
+                }
+                dbot::Error::EmptyIgnore(..) => {
+                    Some("remove `+ignore` or add patterns to it".into())
+                }
+                dbot::Error::UnknownKey(..) => {
+                    Some("profile files may only contain import, data and profile".into())
+                }
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Formats `err` followed by all its causes.
+pub fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut s = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        s.push_str(": ");
+        s.push_str(&err.to_string());
+        source = err.source();
+    }
+    s
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Diagnostic {
+            message,
+            path,
+            line,
+            column,
+            hint,
+        } = self;
+        writeln!(f, "error: {message}")?;
+        let gutter = " ".repeat(line.to_string().len());
+        writeln!(f, "{gutter}--> {}:{line}:{column}", path.display())?;
+        let code = std::fs::read_to_string(path).ok().and_then(|content| {
+            content
+                .lines()
+                .nth(line.saturating_sub(1))
+                .map(str::to_owned)
+        });
+        if let Some(code) = code {
+            // Underlines the key or the value starting at the column.
+            let start = code
+                .char_indices()
+                .nth(column.saturating_sub(1))
+                .map(|(i, _)| i)
+                .unwrap_or(code.len());
+            let len = code[start..]
+                .chars()
+                .take_while(|c| !c.is_whitespace() && *c != ':')
+                .count()
+                .max(1);
+            let carets = format!(
+                "{}{}",
+                " ".repeat(column.saturating_sub(1)),
+                "^".repeat(len)
+            );
+            writeln!(f, "{gutter} |")?;
+            writeln!(f, "{line} | {code}")?;
+            writeln!(f, "{gutter} | {carets}")?;
+        }
+        if let Some(hint) = hint {
+            writeln!(f, "{gutter} = hint: {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_zero_location() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("dbot.yaml");
+        std::fs::write(&path, "import: []\n").unwrap();
+        let diagnostic = Diagnostic {
+            message: "error".to_owned(),
+            path,
+            line: 0,
+            column: 0,
+            hint: None,
+        };
+        assert!(diagnostic.to_string().contains("import: []"));
+    }
+
+    #[test]
+    fn render_code_frame() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("dbot.yaml");
+        std::fs::write(&path, "profile:\n  .bashrc: <lnk>\n").unwrap();
+        let diagnostic = Diagnostic {
+            message: "unknown attribute type '<lnk>'".to_owned(),
+            path: path.clone(),
+            line: 2,
+            column: 12,
+            hint: Some("expected copy, link or template".into()),
+        };
+        let expected = format!(
+            r#"error: unknown attribute type '<lnk>'
+ --> {}:2:12
+  |
+2 |   .bashrc: <lnk>
+  |            ^^^^^
+  = hint: expected copy, link or template
+"#,
+            path.display()
+        );
+        assert_eq!(diagnostic.to_string(), expected);
+    }
+}
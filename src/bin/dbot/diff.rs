@@ -0,0 +1,153 @@
+use crate::{error, secret, Runtime};
+use dbot::{compile::CompiledProfile, profile::AttrType, TemplateRenderer};
+use similar::TextDiff;
+use std::path::Path;
+use thisctx::WithContext;
+
+impl Runtime {
+    pub fn diff(&self) -> error::Result<()> {
+        let (mut renderer, entries) = self.compile()?;
+        for (target, profile) in entries.iter() {
+            if let Some(diff) = diff_entry(&mut renderer, target, profile)? {
+                print!("{diff}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the difference between `target` and its expected contents, where
+/// revealed secrets are masked.
+pub fn diff_entry<R: TemplateRenderer>(
+    renderer: &mut R,
+    target: &Path,
+    profile: &CompiledProfile,
+) -> error::Result<Option<String>>
+where
+    R::Err: 'static + std::error::Error + Send + Sync,
+{
+    let source = &profile.source;
+    let metadata = target.symlink_metadata().ok();
+    if profile.ty == AttrType::Link {
+        return Ok(match metadata {
+            None => Some(format!("Missing symlink '{}'\n", target.display())),
+            Some(metadata) if metadata.is_symlink() => {
+                let original = std::fs::read_link(target).context(error::Io(target))?;
+                let expected = profile.link_original(target);
+                if original == expected {
+                    None
+                } else {
+                    Some(format!(
+                        "Symlink points elsewhere at '{}': '{}' (expected '{}')\n",
+                        target.display(),
+                        original.display(),
+                        expected.display(),
+                    ))
+                }
+            }
+            Some(_) => Some(format!("Not a symlink at '{}'\n", target.display())),
+        });
+    }
+
+    // Only files are compared, a directory in place of the target is a conflict.
+    if target.is_dir() {
+        return Ok(Some(format!("Not a file at '{}'\n", target.display())));
+    }
+    let expected = dbot::expected_contents(renderer, target, profile)?;
+    let current = match metadata {
+        Some(_) => std::fs::read(target).context(error::Io(target))?,
+        None => Vec::new(),
+    };
+    if current == expected {
+        return Ok(None);
+    }
+    let old_header = match metadata {
+        Some(_) => target.display().to_string(),
+        None => "/dev/null".to_owned(),
+    };
+    let diff = unified_diff(
+        &old_header,
+        &source.display().to_string(),
+        &current,
+        &expected,
+    );
+    Ok(Some(secret::mask(&diff).into_owned()))
+}
+
+pub fn unified_diff(old_header: &str, new_header: &str, old: &[u8], new: &[u8]) -> String {
+    match (std::str::from_utf8(old), std::str::from_utf8(new)) {
+        (Ok(old), Ok(new)) => TextDiff::from_lines(old, new)
+            .unified_diff()
+            .header(old_header, new_header)
+            .to_string(),
+        _ => format!("Binary files '{old_header}' and '{new_header}' differ\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::TeraRenderer;
+
+    #[test]
+    fn unified_text_diff() {
+        let diff = unified_diff("old", "new", b"a\nb\n", b"a\nc\n");
+        assert_eq!(diff, "--- old\n+++ new\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n");
+    }
+
+    #[test]
+    fn diff_directory_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        std::fs::write(&source, "source").unwrap();
+        std::fs::create_dir(&target).unwrap();
+        let profile = CompiledProfile {
+            source,
+            ty: AttrType::Copy,
+            permissions: Default::default(),
+            relative: false,
+        };
+        let diff = diff_entry(&mut TeraRenderer::default(), &target, &profile).unwrap();
+        assert_eq!(
+            diff,
+            Some(format!("Not a file at '{}'\n", target.display()))
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn diff_masked_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        std::fs::write(&source, r#"token={{ secret(name="diff") }}"#).unwrap();
+        let mut secrets = crate::secret::Secrets::default();
+        secrets.add_backend(
+            "fake",
+            Box::new(crate::secret::CommandBackend::new(vec![
+                "printf".to_owned(),
+                "revealed-{}".to_owned(),
+            ])),
+        );
+        let mut renderer = TeraRenderer::default();
+        renderer.register_secrets(std::sync::Arc::new(secrets));
+        let profile = CompiledProfile {
+            source,
+            ty: AttrType::Template,
+            permissions: Default::default(),
+            relative: false,
+        };
+        let diff = diff_entry(&mut renderer, &target, &profile)
+            .unwrap()
+            .unwrap();
+        assert!(diff.contains("+token=********"), "{diff}");
+        assert!(!diff.contains("revealed-diff"), "{diff}");
+    }
+
+    #[test]
+    fn unified_binary_diff() {
+        let diff = unified_diff("old", "new", b"\xff", b"");
+        assert_eq!(diff, "Binary files 'old' and 'new' differ\n");
+    }
+}
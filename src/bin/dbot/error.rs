@@ -1,8 +1,10 @@
+use dbot::error::Span;
 use std::path::PathBuf;
 use thisctx::WithContext;
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 #[derive(Debug, Error, WithContext)]
 pub enum Error {
@@ -12,6 +14,44 @@ pub enum Error {
     Io(#[source] std::io::Error, PathBuf),
     #[error("Invalid yaml file at '{1}'")]
     Yaml(#[source] serde_yaml::Error, PathBuf),
+    #[error(
+        "Target has been modified since last applied: '{0}', use '--force' to discard changes"
+    )]
+    Modified(PathBuf),
+    #[error("Path is not under the target directory: '{0}'")]
+    NotUnderTarget(PathBuf),
+    #[error("Target is not managed by any profile entry: '{0}'")]
+    NotManaged(PathBuf),
+    #[error("Target is already managed: '{0}'")]
+    AlreadyManaged(PathBuf),
+    #[error("Source already exists: '{0}'")]
+    SourceExists(PathBuf),
+    #[error("Cannot insert into the profile at '{0}', please edit it manually")]
+    UneditableProfile(PathBuf),
+    #[error("Import cycle found: {0}")]
+    ImportCycle(String, Option<Span>),
+    #[error("Invalid import pattern '{1}'")]
+    InvalidImport(#[source] globset::Error, String, Option<Span>),
+    #[error("Cannot import '{1}'")]
+    BadImport(#[source] BoxError, PathBuf, Option<Span>),
+    #[error("Invalid templates in '{1}'")]
+    Templates(#[source] tera::Error, PathBuf),
+    #[error("Cannot format template variables")]
+    Format(#[source] BoxError),
+    #[error("Failed to run '{1}'")]
+    Command(#[source] std::io::Error, String),
+    #[error("Command '{0}' failed with {1}: {2}")]
+    CommandFailed(String, std::process::ExitStatus, String),
+    #[error("Secret backend '{0}' is not configured")]
+    UnknownSecretBackend(String),
+    #[error("No secret backend is specified for '{0}' and there is no default one")]
+    NoSecretBackend(String),
+    #[error("Secret file not found: '{0}'")]
+    SecretNotFound(PathBuf),
+    #[error("Secret name must be a relative path without '..': '{0}'")]
+    InvalidSecretName(String),
+    #[error("Found {0} problem(s) in the profile")]
+    CheckFailed(usize),
     #[error(transparent)]
     Dbot(#[from] dbot::Error),
 }
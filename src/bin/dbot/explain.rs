@@ -0,0 +1,78 @@
+use crate::{add::resolve_target, error, Runtime};
+use dbot::profile::Origin;
+use std::path::Path;
+use thisctx::IntoError;
+
+fn origins(origins: &[Origin]) -> String {
+    if origins.is_empty() {
+        return "default".to_owned();
+    }
+    origins
+        .iter()
+        .map(Origin::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Runtime {
+    pub fn explain(&self, path: &Path) -> error::Result<()> {
+        let options = self.options()?;
+        let (target, relative) = resolve_target(options.target(), path)?;
+        let Some(entries) = self
+            .load_profile(options.source())?
+            .content
+            .profile
+            .map(|profile| profile.into_entries())
+            .transpose()?
+        else {
+            return error::NotManaged(&target).fail();
+        };
+        let Some((node, attr)) = entries.find(&relative) else {
+            return error::NotManaged(&target).fail();
+        };
+        let (node, attr) = (node.to_owned(), attr.clone());
+        // Compiles the same entries to avoid loading the profile twice.
+        let compiled = self.compile_entries(entries)?;
+
+        println!("{:<16}{}", "target", target.display());
+        println!("{:<16}{}", "profile", node.display());
+        let print = |name: &str, value: &dyn std::fmt::Display| {
+            println!("{name:<16}{value} ({})", origins(attr.origins.get(name)));
+        };
+        match compiled.get(&target) {
+            Some(compiled) => {
+                print("source", &compiled.source.display());
+                print("type", &compiled.ty);
+            }
+            // Either ignored or not found in the source directory.
+            None => {
+                print("source", &attr.source.display());
+                print("type", &attr.ty);
+                println!("{:<16}not produced by this entry", "");
+            }
+        }
+        print("recursive", &attr.recursive);
+        print(
+            "ignore",
+            &format!(
+                "[{}]",
+                attr.ignore.patterns().collect::<Vec<_>>().join(", ")
+            ),
+        );
+        let permissions = &attr.permissions;
+        if let Some(mode) = permissions.mode {
+            print("mode", &format!("{mode:04o}"));
+        }
+        for (name, value) in [
+            ("executable", Some(permissions.executable)),
+            ("private", Some(permissions.private)),
+            ("preserve_mode", permissions.preserve_mode),
+            ("relative", attr.relative),
+        ] {
+            if let Some(value) = value.filter(|_| !attr.origins.get(name).is_empty()) {
+                print(name, &value);
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
-use dbot::compile::CompiledEntries;
+use dbot::{compile::CompiledEntries, Fingerprint};
 use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf};
 use time::OffsetDateTime;
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -11,6 +12,12 @@ pub struct HistoryManager {
 pub struct Entry {
     pub timespan: OffsetDateTime,
     pub entries: CompiledEntries,
+    /// Target files replaced last time and where they have been moved to.
+    #[serde(default)]
+    pub backups: BTreeMap<PathBuf, PathBuf>,
+    /// States of target files when they were applied.
+    #[serde(default)]
+    pub fingerprints: BTreeMap<PathBuf, Fingerprint>,
 }
 
 impl HistoryManager {
@@ -18,14 +25,25 @@ impl HistoryManager {
         self.entries.last()
     }
 
+    pub fn last_mut(&mut self) -> Option<&mut Entry> {
+        self.entries.last_mut()
+    }
+
     pub fn pop(&mut self) -> Option<Entry> {
         self.entries.pop()
     }
 
-    pub fn push(&mut self, entries: CompiledEntries) {
+    pub fn push(
+        &mut self,
+        entries: CompiledEntries,
+        backups: BTreeMap<PathBuf, PathBuf>,
+        fingerprints: BTreeMap<PathBuf, Fingerprint>,
+    ) {
         self.entries.push(Entry {
             timespan: OffsetDateTime::now_utc(),
             entries,
+            backups,
+            fingerprints,
         });
     }
 }
@@ -1,28 +1,46 @@
 use clap::Parser;
 use cli::{Cli, Command};
-use dbot::{compile::CompilerOptions, Merge};
+use dbot::{
+    compile::CompiledEntries,
+    profile::{Host, ProfileEntries},
+    Fingerprint, Merge, Transaction,
+};
+use diagnostic::Diagnostic;
 use directories::{BaseDirs, ProjectDirs};
 use history::HistoryManager;
 use once_cell::unsync::OnceCell;
 use options::Options;
+use profile::Profile;
+use secret::{BackendConfig, MaskedStdout, Secrets};
 use std::{
+    collections::BTreeMap,
     io::Write,
     path::{Path, PathBuf},
+    sync::Arc,
 };
-use template::TeraRenderer;
-use thisctx::WithContext;
-use tracing::info;
+use template::{Builtins, TeraRenderer, K_BUILTINS};
+use thisctx::{IntoError, WithContext};
+use tracing::{info, warn};
 
+mod add;
+mod check;
 mod cli;
+mod data;
+mod diagnostic;
+mod diff;
 mod error;
+mod explain;
 mod history;
 mod options;
 mod profile;
+mod re_add;
+mod secret;
 mod template;
 
 const F_CONFIG: &str = "config.yaml";
 const F_HISTORY: &str = "history.yaml";
 const F_PROFILE: &str = "dbot.yaml";
+const D_BACKUPS: &str = "backups";
 
 struct Dirs {
     home: PathBuf,
@@ -76,6 +94,21 @@ impl Runtime {
             opts.target
                 .get_or_insert_with(|| dirs.home.clone())
                 .expand_tilde(&dirs.home);
+            opts.backup_dir
+                .get_or_insert_with(|| dirs.data.join(D_BACKUPS))
+                .expand_tilde(&dirs.home);
+            if let Some(dir) = opts.templates_dir.as_mut() {
+                dir.expand_tilde(&dirs.home);
+            }
+            for backend in opts
+                .secrets
+                .iter_mut()
+                .flat_map(|secrets| secrets.values_mut())
+            {
+                if let BackendConfig::File { dir, .. } = backend {
+                    dir.expand_tilde(&dirs.home);
+                }
+            }
             Ok(opts)
         })
     }
@@ -116,38 +149,130 @@ impl Runtime {
         Ok(self.history.get_mut().unwrap())
     }
 
-    fn apply(&mut self) -> error::Result<()> {
-        // TODO: remove files on conflicts
-        self.clean()?;
+    fn renderer(&self, profile: &Profile) -> error::Result<TeraRenderer> {
         let options = self.options()?;
-        let source = options.source();
-        let target = options.target();
-        let profile = self.load_profile(source)?;
+        let home = &self.dirs()?.home;
+        let builtins = Builtins::new(Host::current(), home, options.source(), options.target());
         let mut renderer = TeraRenderer::default();
+        renderer.add_templates(&options.templates_dir())?;
+        renderer.register_functions(home, options.source(), options.target());
+        renderer.register_secrets(Arc::new(
+            options
+                .secrets
+                .as_ref()
+                .map(Secrets::new)
+                .unwrap_or_default(),
+        ));
         renderer.add_data("data", &profile.content.data);
-        let entries = dbot::compile(
-            &CompilerOptions { source, target },
-            profile.content.profile.unwrap().into_entries()?,
-        )?;
-        dbot::apply(&mut renderer, &entries)?;
-        self.history_mut()?.push(entries);
+        renderer.add_data(K_BUILTINS, &builtins);
+        Ok(renderer)
+    }
+
+    fn compile(&self) -> error::Result<(TeraRenderer, CompiledEntries)> {
+        let profile = self.load_profile(self.options()?.source())?;
+        let renderer = self.renderer(&profile)?;
+        let entries = self.compile_entries(profile.content.profile.unwrap().into_entries()?)?;
+        Ok((renderer, entries))
+    }
+
+    fn compile_entries(&self, entries: ProfileEntries) -> error::Result<CompiledEntries> {
+        Ok(dbot::compile(&self.options()?.compiler_options(), entries)?)
+    }
+
+    fn apply(&mut self, dry_run: bool, force: bool) -> error::Result<()> {
+        if dry_run {
+            return self.plan();
+        }
+        let (mut renderer, mut entries) = self.compile()?;
+        let (mut backups, applied) = Transaction::run(|transaction| {
+            let backups = self.clean_in(transaction, force, Some(&entries))?;
+            let options = self.options()?.apply_options();
+            let applied = dbot::apply_in(transaction, &mut renderer, &entries, &options)?;
+            Ok::<_, error::Error>((backups, applied))
+        })?;
+        for target in applied.skipped.iter() {
+            info!("Skip conflicting target '{}'", target.display());
+            entries.remove(target);
+        }
+        backups.extend(applied.backups);
+        self.history_mut()?
+            .push(entries, backups, applied.fingerprints);
         self.save_histroy()?;
         Ok(())
     }
 
-    fn clean(&mut self) -> error::Result<()> {
-        let Some(last) = self.history_mut()?.pop() else { return Ok(()) };
+    fn plan(&self) -> error::Result<()> {
+        let (mut renderer, entries) = self.compile()?;
+        let previous = self.history()?.last().map(|last| &last.entries);
+        let plan = dbot::plan(&mut renderer, &entries, previous)?;
+        for (target, action) in plan.iter() {
+            println!("{:<16}{}", action, target.display());
+        }
+        Ok(())
+    }
+
+    fn clean(&mut self, force: bool) -> error::Result<()> {
+        Transaction::run(|transaction| self.clean_in(transaction, force, None))?;
+        self.save_histroy()
+    }
+
+    /// Removes target files created last time and restores their backups,
+    /// except those of targets still in `keep`, which are returned to be
+    /// restored by a later clean.
+    fn clean_in(
+        &mut self,
+        transaction: &mut Transaction,
+        force: bool,
+        keep: Option<&CompiledEntries>,
+    ) -> error::Result<BTreeMap<PathBuf, PathBuf>> {
+        let mut kept = BTreeMap::new();
+        let Some(last) = self.history_mut()?.pop() else {
+            return Ok(kept);
+        };
         info!("Clean history created at '{}'", last.timespan);
-        for (target, _) in last.entries.iter() {
-            if target.exists() {
-                std::fs::remove_file(target).context(error::Io(&target))?;
+        for (target, _) in last.entries.iter_rev() {
+            let Some(current) = Fingerprint::of(target)? else {
+                continue;
+            };
+            if let Some(recorded) = last.fingerprints.get(target) {
+                if !force && !current.same_contents(recorded) {
+                    return error::Modified(target).fail();
+                }
+            }
+            transaction.remove(target)?;
+        }
+        // Restore target files replaced last time.
+        for (target, backup) in last.backups.into_iter() {
+            if !backup.exists() {
+                warn!("Backup of '{}' is missing", target.display());
+                continue;
+            }
+            if keep.is_some_and(|keep| keep.get(&target).is_some()) {
+                kept.insert(target, backup);
+                continue;
             }
+            transaction.rename(&backup, &target)?;
+        }
+        Ok(kept)
+    }
+
+    fn status(&self) -> error::Result<()> {
+        let Some(last) = self.history()?.last() else {
+            return Ok(());
+        };
+        let profile = self.load_profile(self.options()?.source())?;
+        let mut renderer = self.renderer(&profile)?;
+        let statuses = dbot::status(&mut renderer, &last.entries, &last.fingerprints)?;
+        for (target, status) in statuses {
+            println!("{:<20}{}", status, target.display());
         }
         Ok(())
     }
 
     fn ls(&self) -> error::Result<()> {
-        let Some(last) = self.history()?.last() else { return Ok(()) };
+        let Some(last) = self.history()?.last() else {
+            return Ok(());
+        };
         for (target, _) in last.entries.iter() {
             println!("{}", target.display());
         }
@@ -156,16 +281,97 @@ impl Runtime {
 }
 
 fn main() -> anyhow::Result<()> {
-    let subscriber = tracing_subscriber::FmtSubscriber::new();
+    // Secrets may be revealed by templates and must never be logged.
+    let subscriber = tracing_subscriber::FmtSubscriber::builder()
+        .with_writer(|| MaskedStdout)
+        .finish();
     tracing::subscriber::set_global_default(subscriber)?;
     let args = Cli::parse();
     let mut rt = Runtime::default();
     // Override default options.
     rt.options_mut()?.merge(args.options);
-    match args.cmd {
-        Command::Apply {} => rt.apply()?,
-        Command::Clean {} => rt.clean()?,
+    if let Err(e) = run(&mut rt, args.cmd) {
+        // Render errors located in profile files as code frames.
+        let source = rt.options().ok().and_then(|options| options.source.clone());
+        if let Some(diagnostic) = source.and_then(|source| Diagnostic::new(&e, &source)) {
+            eprint!("{diagnostic}");
+            std::process::exit(1);
+        }
+        let e = anyhow::Error::from(e);
+        eprintln!("Error: {}", secret::mask(&format!("{e:?}")));
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn run(rt: &mut Runtime, cmd: Command) -> error::Result<()> {
+    match cmd {
+        Command::Add { path, ty, replace } => rt.add(&path, ty, replace)?,
+        Command::Apply { dry_run, force } => rt.apply(dry_run, force)?,
+        Command::Plan {} => rt.plan()?,
+        Command::Diff {} => rt.diff()?,
+        Command::Clean { force } => rt.clean(force)?,
+        Command::ReAdd { all } => rt.re_add(all)?,
+        Command::Status {} => rt.status()?,
         Command::Ls {} => rt.ls()?,
+        Command::Explain { path } => rt.explain(&path)?,
+        Command::Check {} => rt.check()?,
+        Command::Data { format } => rt.data(format)?,
+        Command::Schema {} => println!("{:#}", dbot::profile::schema()),
     }
     Ok(())
 }
+
+#[cfg(test)]
+impl Runtime {
+    /// Creates a runtime whose home, source and data directories are all
+    /// under `root`.
+    fn new_in(root: &Path) -> Self {
+        let dirs = Dirs {
+            home: root.join("home"),
+            config: root.join("config"),
+            data: root.join("data"),
+        };
+        let options = Options {
+            source: Some(root.join("source")),
+            target: Some(dirs.home.clone()),
+            backup_dir: Some(dirs.data.join(D_BACKUPS)),
+            ..Default::default()
+        };
+        Runtime {
+            dirs: OnceCell::with_value(dirs),
+            options: OnceCell::with_value(options),
+            history: OnceCell::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbot::ConflictPolicy;
+
+    #[test]
+    fn reapply_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let target = dir.path().join("home/.bashrc");
+        std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join(F_PROFILE), "profile: { .bashrc: bashrc }").unwrap();
+        std::fs::write(source.join("bashrc"), "applied").unwrap();
+        std::fs::write(&target, "original").unwrap();
+
+        let mut rt = Runtime::new_in(dir.path());
+        rt.options_mut().unwrap().conflict = Some(ConflictPolicy::Backup);
+        rt.apply(false, false).unwrap();
+        rt.apply(false, false).unwrap();
+        // The backup is no longer restored before applying again.
+        rt.options_mut().unwrap().conflict = None;
+        rt.apply(false, false).unwrap();
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "applied");
+
+        rt.clean(false).unwrap();
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "original");
+    }
+}
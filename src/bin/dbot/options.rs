@@ -1,9 +1,20 @@
+use crate::secret::BackendConfig;
 use clap::Args;
-use dbot::Merge;
+use dbot::{compile::CompilerOptions, ApplyOptions, ConflictPolicy, Merge};
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 const V_PATH: &str = "PATH";
+const V_POLICY: &str = "POLICY";
+const V_SUFFIX: &str = "SUFFIX";
+const V_BOOL: &str = "BOOL";
+
+const DEFAULT_RENAME_SUFFIX: &str = ".bak";
+/// Default directory of shared templates, relative to the source directory.
+const DEFAULT_TEMPLATES_DIR: &str = ".dbot/templates";
 
 #[derive(Debug, Default, Deserialize, Args, Serialize)]
 pub struct Options {
@@ -11,12 +22,55 @@ pub struct Options {
     pub source: Option<PathBuf>,
     #[arg(long, value_name(V_PATH))]
     pub target: Option<PathBuf>,
+    /// How to resolve existing target files: error, overwrite, skip, backup
+    /// or rename.
+    #[arg(long, value_name(V_POLICY))]
+    pub conflict: Option<ConflictPolicy>,
+    /// Directory to store backups of conflicting target files.
+    #[arg(long, value_name(V_PATH))]
+    pub backup_dir: Option<PathBuf>,
+    /// Suffix appended to conflicting target files when renaming them.
+    #[arg(long, value_name(V_SUFFIX))]
+    pub rename_suffix: Option<String>,
+    /// Directory of templates shared by other templates, which are never
+    /// deployed. Relative paths are relative to the source directory.
+    #[arg(long, value_name(V_PATH))]
+    pub templates_dir: Option<PathBuf>,
+    /// Create symlinks relative to their parent directories by default.
+    #[arg(long, value_name(V_BOOL), num_args(0..=1), require_equals(true), default_missing_value("true"))]
+    pub relative_links: Option<bool>,
+    /// Let later profile entries override earlier ones producing the same
+    /// target instead of failing.
+    #[arg(long, value_name(V_BOOL), num_args(0..=1), require_equals(true), default_missing_value("true"))]
+    pub later_wins: Option<bool>,
+    /// Treat undefined attributes, unknown keys, empty ignore sets and missing
+    /// sources of entries skipped on this machine as errors.
+    #[arg(
+        long,
+        value_name(V_BOOL),
+        num_args(0..=1),
+        require_equals(true),
+        default_missing_value("true")
+    )]
+    pub strict: Option<bool>,
+    /// Named backends providing secrets to templates, which can only be
+    /// configured in the config file.
+    #[arg(skip)]
+    pub secrets: Option<BTreeMap<String, BackendConfig>>,
 }
 
 impl Merge for Options {
     fn merge(&mut self, other: Self) {
         self.source.merge(other.source);
         self.target.merge(other.target);
+        self.conflict.merge(other.conflict);
+        self.backup_dir.merge(other.backup_dir);
+        self.rename_suffix.merge(other.rename_suffix);
+        self.templates_dir.merge(other.templates_dir);
+        self.relative_links.merge(other.relative_links);
+        self.later_wins.merge(other.later_wins);
+        self.strict.merge(other.strict);
+        self.secrets.merge(other.secrets);
     }
 }
 
@@ -34,4 +88,42 @@ impl Options {
     pub fn target(&self) -> &Path {
         self.target.as_deref().unwrap()
     }
+
+    /// # Panic
+    ///
+    /// Panics when `source` is `None`.
+    pub fn templates_dir(&self) -> PathBuf {
+        self.source().join(
+            self.templates_dir
+                .as_deref()
+                .unwrap_or(DEFAULT_TEMPLATES_DIR.as_ref()),
+        )
+    }
+
+    /// # Panic
+    ///
+    /// Panics when `backup_dir` is `None`.
+    pub fn apply_options(&self) -> ApplyOptions<'_> {
+        ApplyOptions {
+            conflict: self.conflict.unwrap_or_default(),
+            backup_dir: self.backup_dir.as_deref().unwrap(),
+            rename_suffix: self
+                .rename_suffix
+                .as_deref()
+                .unwrap_or(DEFAULT_RENAME_SUFFIX),
+        }
+    }
+
+    /// # Panic
+    ///
+    /// Panics when `source` or `target` is `None`.
+    pub fn compiler_options(&self) -> CompilerOptions<'_> {
+        CompilerOptions {
+            source: self.source(),
+            target: self.target(),
+            relative_links: self.relative_links.unwrap_or_default(),
+            later_wins: self.later_wins.unwrap_or_default(),
+            excluded: vec![self.templates_dir()],
+        }
+    }
 }
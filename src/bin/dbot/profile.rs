@@ -1,16 +1,56 @@
 use crate::{error, Runtime, F_PROFILE};
-use dbot::Merge;
+use dbot::{
+    error::{Diagnostics, Span},
+    profile::{locate_key, normalize_path, Conditions, Host},
+    Merge,
+};
+use globset::GlobBuilder;
 use serde::Deserialize;
-use std::path::{Path, PathBuf};
-use thisctx::WithContext;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+use thisctx::{IntoError, WithContext};
+
+/// Key of imported files in profile files.
+const K_IMPORT: &str = "import";
+/// Key of the profile in profile files.
+const K_PROFILE: &str = "profile";
+/// Keys allowed at the top level of profile files.
+const PROFILE_KEYS: &[&str] = &[K_IMPORT, "data", K_PROFILE];
 
-#[derive(Deserialize)]
 pub struct Profile {
-    import: Vec<PathBuf>,
-    #[serde(flatten)]
     pub content: ProfileContent,
 }
 
+#[derive(Deserialize)]
+struct Imports {
+    #[serde(default)]
+    import: Vec<Import>,
+}
+
+/// A profile file to import, which is either a path or a mapping with the
+/// path, conditions to import it and where to mount it.
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a path or a mapping with a path")]
+enum Import {
+    Path(String),
+    Entry(ImportEntry),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImportEntry {
+    path: String,
+    when: Option<Conditions>,
+    /// Prefix of targets defined in the imported profile.
+    #[serde(default)]
+    target: PathBuf,
+    /// Prefix of sources defined in the imported profile.
+    #[serde(default)]
+    source: PathBuf,
+}
+
 #[derive(Deserialize)]
 pub struct ProfileContent {
     pub data: Option<serde_yaml::Mapping>,
@@ -19,22 +59,340 @@ pub struct ProfileContent {
 
 impl Merge for ProfileContent {
     fn merge(&mut self, other: Self) {
-        self.data.merge(other.data);
-        self.profile.merge(other.profile);
+        match (&mut self.data, other.data) {
+            (Some(this), Some(other)) => this.merge(other),
+            (this, other) => this.merge(other),
+        }
+        match (&mut self.profile, other.profile) {
+            (Some(this), Some(other)) => this.merge(other),
+            (this, other) => this.merge(other),
+        }
+    }
+}
+
+/// Reads a profile file, where `file` is relative to `source`. Problems
+/// tolerated unless in strict mode are recorded in `diagnostics`.
+fn load_content(
+    source: &Path,
+    file: &Path,
+    strict: bool,
+    diagnostics: &mut Diagnostics,
+) -> error::Result<(String, ProfileContent)> {
+    let path = source.join(file);
+    let content = std::fs::read_to_string(&path).context(error::Io(&path))?;
+    // Deserializes without flattening to keep locations in errors.
+    let mut new = serde_yaml::from_str::<ProfileContent>(&content).context(error::Yaml(&path))?;
+    if let Some(profile) = new.profile.as_mut() {
+        profile.set_source(file, &content, &[K_PROFILE]);
+    }
+    if strict {
+        let mapping =
+            serde_yaml::from_str::<serde_yaml::Mapping>(&content).context(error::Yaml(&path))?;
+        for key in mapping.keys().filter_map(|key| key.as_str()) {
+            if !PROFILE_KEYS.contains(&key) {
+                let span = locate_key(&content, &[key]).map(|location| Span {
+                    file: file.to_owned(),
+                    location,
+                });
+                diagnostics.push(dbot::Error::UnknownKey(key.to_owned(), span));
+            }
+        }
+        if let Some(profile) = new.profile.as_ref() {
+            profile.validate(diagnostics);
+        }
+    }
+    Ok((content, new))
+}
+
+/// Loads profile files and all files imported by them recursively.
+struct Loader<'a> {
+    source: &'a Path,
+    strict: bool,
+    host: Host,
+    /// Files being loaded, used to detect import cycles.
+    stack: Vec<(PathBuf, PathBuf)>,
+    diagnostics: &'a mut Diagnostics,
+}
+
+impl Loader<'_> {
+    /// Loads `file` relative to the source directory, where imported files
+    /// override the importing one in order. `imported_at` is where the file
+    /// is imported, which is `None` for the root profile file.
+    fn load(&mut self, file: &Path, imported_at: Option<&Span>) -> error::Result<ProfileContent> {
+        let path = self.source.join(file);
+        let real = match (path.canonicalize(), imported_at) {
+            (Ok(real), _) => real,
+            (Err(e), Some(span)) => {
+                return Err(Box::new(e) as Box<_>)
+                    .context(error::BadImport(file, Some(span.clone())))
+            }
+            (Err(e), None) => return Err(e).context(error::Io(&path)),
+        };
+        if let Some(i) = self.stack.iter().position(|(_, r)| r == &real) {
+            let cycle = self.stack[i..]
+                .iter()
+                .map(|(file, _)| file.as_path())
+                .chain([file])
+                .map(|file| file.display().to_string())
+                .collect::<Vec<_>>();
+            return error::ImportCycle(cycle.join(" -> "), imported_at.cloned()).fail();
+        }
+        self.stack.push((file.to_owned(), real));
+        let (content, mut new) = load_content(self.source, file, self.strict, self.diagnostics)?;
+        let Imports { import } =
+            serde_yaml::from_str::<Imports>(&content).context(error::Yaml(&path))?;
+        // Errors of imports are located at the key, since entries of the
+        // list are not located.
+        let span = locate_key(&content, &[K_IMPORT]).map(|location| Span {
+            file: file.to_owned(),
+            location,
+        });
+        let base = file.parent().unwrap_or(Path::new(""));
+        for import in import {
+            let entry = match import {
+                Import::Path(path) => ImportEntry {
+                    path,
+                    when: None,
+                    target: PathBuf::new(),
+                    source: PathBuf::new(),
+                },
+                Import::Entry(entry) => entry,
+            };
+            if entry.when.is_some_and(|when| !when.matches(&self.host)) {
+                continue;
+            }
+            for file in resolve_import(self.source, base, &entry.path, span.as_ref())? {
+                let mut imported = self.load(&file, span.as_ref())?;
+                // Mounts before merging, so that the imported profile can be
+                // merged with nodes defined at the same targets.
+                if let Some(profile) = imported.profile.as_mut() {
+                    profile.mount(&entry.target, &entry.source)?;
+                }
+                new.merge(imported);
+            }
+        }
+        self.stack.pop();
+        Ok(new)
+    }
+}
+
+/// Resolves an import relative to the directory `base` of the importing file,
+/// where globs are expanded to matched files sorted by their paths.
+fn resolve_import(
+    source: &Path,
+    base: &Path,
+    pattern: &str,
+    span: Option<&Span>,
+) -> error::Result<Vec<PathBuf>> {
+    let mut pattern = base.join(pattern);
+    if pattern.is_relative() {
+        pattern = normalize_path(&pattern)
+            .map_err(|e| Box::new(e) as Box<_>)
+            .context(error::BadImport(&pattern, span.cloned()))?;
+    }
+    let is_glob = |s: &str| s.contains(['*', '?', '[', '{']);
+    // Expands globs under the longest directory without any of them.
+    let dir = pattern
+        .components()
+        .take_while(|compo| !is_glob(&compo.as_os_str().to_string_lossy()))
+        .collect::<PathBuf>();
+    if dir == pattern {
+        return Ok(vec![pattern]);
+    }
+    let glob = pattern.to_string_lossy();
+    let matcher = GlobBuilder::new(&glob)
+        .literal_separator(true)
+        .build()
+        .context(error::InvalidImport(&*glob, span.cloned()))?
+        .compile_matcher();
+    let mut files = Vec::new();
+    let mut dirs = vec![dir];
+    while let Some(dir) = dirs.pop() {
+        let path = source.join(&dir);
+        if !path.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&path).context(error::Io(&path))? {
+            let entry = entry.context(error::Io(&path))?;
+            let file = dir.join(entry.file_name());
+            if entry.path().is_dir() {
+                dirs.push(file);
+            } else if matcher.is_match(&file) {
+                files.push(file);
+            }
+        }
     }
+    files.sort();
+    Ok(files)
 }
 
 impl Runtime {
     pub fn load_profile(&self, source: &Path) -> error::Result<Profile> {
-        let path = source.join(F_PROFILE);
-        let content = std::fs::read_to_string(&path).context(error::Io(&path))?;
-        let mut new = serde_yaml::from_str::<Profile>(&content).context(error::Yaml(&path))?;
-        for file in new.import.iter() {
-            let content = std::fs::read_to_string(source.join(file)).context(error::Io(&file))?;
-            let import =
-                serde_yaml::from_str::<ProfileContent>(&content).context(error::Yaml(&file))?;
-            new.content.merge(import);
+        let mut diagnostics = Diagnostics::default();
+        let profile = self.load_profile_checked(source, &mut diagnostics)?;
+        Ok(diagnostics.into_result(profile)?)
+    }
+
+    /// Loads the profile, where problems found in strict mode are recorded in
+    /// `diagnostics`.
+    pub fn load_profile_checked(
+        &self,
+        source: &Path,
+        diagnostics: &mut Diagnostics,
+    ) -> error::Result<Profile> {
+        let strict = self.options()?.strict.unwrap_or_default();
+        let mut loader = Loader {
+            source,
+            strict,
+            host: Host::current(),
+            stack: Vec::new(),
+            diagnostics,
+        };
+        let new = loader.load(F_PROFILE.as_ref(), None)?;
+        if let (true, Some(profile)) = (strict, new.profile.as_ref()) {
+            check_skipped_sources(source, profile, diagnostics);
         }
-        Ok(new)
+        Ok(Profile { content: new })
+    }
+}
+
+/// Checks sources of entries skipped on the current machine, which would
+/// otherwise go unnoticed until applied elsewhere.
+fn check_skipped_sources(source: &Path, profile: &dbot::Profile, diagnostics: &mut Diagnostics) {
+    // Errors of the entries themselves are reported when compiling.
+    let mut ignored = Diagnostics::default();
+    let active = profile
+        .clone()
+        .collect_entries(Some(&Host::current()), &mut ignored)
+        .iter()
+        .map(|(target, _)| target.to_owned())
+        .collect::<HashSet<_>>();
+    let all = profile.clone().collect_entries(None, &mut ignored);
+    for (_, attr) in all.iter().filter(|(target, _)| !active.contains(*target)) {
+        let path = source.join(&attr.source);
+        if !path.exists() {
+            let span = attr.origins.span("source");
+            diagnostics.push(dbot::Error::MissingSource(path, span));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Diagnostic;
+
+    fn write_files(dir: &Path, files: &[(&str, &str)]) {
+        for (file, content) in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+    }
+
+    fn load(source: &Path, host: Host) -> error::Result<ProfileContent> {
+        let mut diagnostics = Diagnostics::default();
+        Loader {
+            source,
+            strict: false,
+            host,
+            stack: Vec::new(),
+            diagnostics: &mut diagnostics,
+        }
+        .load(F_PROFILE.as_ref(), None)
+    }
+
+    #[test]
+    fn nested_imports() {
+        let tmp = tempfile::tempdir().unwrap();
+        write_files(
+            tmp.path(),
+            &[
+                (
+                    "dbot.yaml",
+                    "import: [imports/a.yaml, { path: imports/c.yaml, when: { os: plan9 } }]",
+                ),
+                (
+                    "imports/a.yaml",
+                    "import: ['sub/*.yaml']
+data: { a: 1 }",
+                ),
+                ("imports/sub/b1.yaml", "data: { a: 2, b: 1 }"),
+                ("imports/sub/b2.yaml", "data: { b: 2 }"),
+                ("imports/sub/b3.yml", "data: { b: 3 }"),
+                ("imports/c.yaml", "data: { c: 1 }"),
+            ],
+        );
+        let host = Host {
+            os: "linux".to_owned(),
+            ..Default::default()
+        };
+        let data = load(tmp.path(), host).unwrap().data.unwrap();
+        let expected = serde_yaml::from_str::<serde_yaml::Mapping>("{ a: 2, b: 2 }").unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn import_cycle() {
+        let tmp = tempfile::tempdir().unwrap();
+        write_files(
+            tmp.path(),
+            &[
+                ("dbot.yaml", "import: [imports/a.yaml]"),
+                ("imports/a.yaml", "import: [b.yaml]"),
+                ("imports/b.yaml", "import: [../dbot.yaml]"),
+            ],
+        );
+        let err = load(tmp.path(), Host::default()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Import cycle found: dbot.yaml -> imports/a.yaml -> imports/b.yaml -> dbot.yaml"
+        );
+        let diagnostic = Diagnostic::new(&err, tmp.path()).unwrap().to_string();
+        assert!(diagnostic.contains("imports/b.yaml:1:1"), "{diagnostic}");
+    }
+
+    #[test]
+    fn bad_imports() {
+        let tmp = tempfile::tempdir().unwrap();
+        for import in ["missing.yaml", "../outside.yaml", "'[*.yaml'"] {
+            write_files(
+                tmp.path(),
+                &[("dbot.yaml", &format!("data: {{}}\nimport: [{import}]"))],
+            );
+            let err = load(tmp.path(), Host::default()).err().unwrap();
+            let diagnostic = Diagnostic::new(&err, tmp.path())
+                .unwrap_or_else(|| panic!("{import}: {err}"))
+                .to_string();
+            assert!(diagnostic.contains("dbot.yaml:2:1"), "{diagnostic}");
+        }
+    }
+
+    #[test]
+    fn mounted_imports() {
+        let tmp = tempfile::tempdir().unwrap();
+        write_files(
+            tmp.path(),
+            &[
+                (
+                    "dbot.yaml",
+                    "import: [{ path: nvim.yaml, target: .config/nvim, source: nvim/ }]",
+                ),
+                ("nvim.yaml", "profile: { init.lua: init.lua, lua: <link> }"),
+            ],
+        );
+        let profile = load(tmp.path(), Host::default()).unwrap().profile.unwrap();
+        let entries = profile.into_entries().unwrap();
+        let entries = entries
+            .iter()
+            .map(|(target, attr)| (target.to_str().unwrap(), attr.source.to_str().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                (".config/nvim/init.lua", "nvim/init.lua"),
+                (".config/nvim/lua", "nvim/lua"),
+            ]
+        );
     }
 }
@@ -0,0 +1,207 @@
+use crate::{diff::unified_diff, error, secret, Runtime};
+use dbot::{compile::CompiledProfile, profile::AttrType, Fingerprint, TemplateRenderer};
+use std::{
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+use thisctx::WithContext;
+use tracing::info;
+
+fn confirm(prompt: &str) -> std::io::Result<bool> {
+    let mut stdout = std::io::stdout();
+    write!(stdout, "{prompt} [y/N] ")?;
+    stdout.flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Returns the difference from what applying `profile` creates to the
+/// current `target`, where added lines are those copied back into the source.
+fn re_add_diff<R: TemplateRenderer>(
+    renderer: &mut R,
+    target: &Path,
+    profile: &CompiledProfile,
+) -> error::Result<Option<String>>
+where
+    R::Err: 'static + std::error::Error + Send + Sync,
+{
+    let expected = dbot::expected_contents(renderer, target, profile)?;
+    let current = std::fs::read(target).context(error::Io(target))?;
+    if current == expected {
+        return Ok(None);
+    }
+    let diff = unified_diff(
+        &profile.source.display().to_string(),
+        &target.display().to_string(),
+        &expected,
+        &current,
+    );
+    Ok(Some(secret::mask(&diff).into_owned()))
+}
+
+impl Runtime {
+    pub fn re_add(&mut self, all: bool) -> error::Result<()> {
+        let Some(last) = self.history()?.last() else {
+            return Ok(());
+        };
+        let entries = last
+            .entries
+            .iter()
+            .filter(|(_, profile)| profile.ty != AttrType::Link)
+            .map(|(target, profile)| (target.to_owned(), profile.clone()))
+            .collect::<Vec<(PathBuf, CompiledProfile)>>();
+        let profile = self.load_profile(self.options()?.source())?;
+        let mut renderer = self.renderer(&profile)?;
+
+        let mut changed = false;
+        for (target, profile) in entries {
+            if !target.is_file() {
+                continue;
+            }
+            let Some(diff) = re_add_diff(&mut renderer, &target, &profile)? else {
+                continue;
+            };
+            if profile.ty == AttrType::Template {
+                println!(
+                    "Cannot re-add '{}', edit the template '{}' instead",
+                    target.display(),
+                    profile.source.display(),
+                );
+                print!("{diff}");
+                continue;
+            }
+            if !all {
+                print!("{diff}");
+                let prompt = format!("Re-add '{}'?", target.display());
+                if !confirm(&prompt).context(error::Io(&target))? {
+                    continue;
+                }
+            }
+            std::fs::copy(&target, &profile.source).context(error::Io(&profile.source))?;
+            info!(
+                "Re-add '{}' to '{}'",
+                target.display(),
+                profile.source.display()
+            );
+            // The target is in sync with its source again.
+            if let Some(fingerprint) = Fingerprint::of(&target)? {
+                if let Some(last) = self.history_mut()?.last_mut() {
+                    last.fingerprints.insert(target, fingerprint);
+                }
+            }
+            changed = true;
+        }
+        if changed {
+            self.save_histroy()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies a profile with a copy, a template and a link under `dir`.
+    #[cfg(unix)]
+    fn applied(dir: &Path) -> Runtime {
+        let source = dir.join("source");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(
+            source.join(crate::F_PROFILE),
+            r#"
+            profile:
+              .copied: copied
+              .rendered: { +source: rendered, +type: template }
+              .linked: { +source: linked, +type: link }
+            "#,
+        )
+        .unwrap();
+        for name in ["copied", "rendered", "linked"] {
+            std::fs::write(source.join(name), "source\n").unwrap();
+        }
+        let mut rt = Runtime::new_in(dir);
+        rt.apply(false, false).unwrap();
+        rt
+    }
+
+    #[cfg(unix)]
+    fn read(path: PathBuf) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn re_add_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rt = applied(dir.path());
+        let target = dir.path().join("home/.copied");
+        std::fs::write(&target, "target\n").unwrap();
+        rt.re_add(true).unwrap();
+        assert_eq!(read(dir.path().join("source/copied")), "target\n");
+        let last = rt.history().unwrap().last().unwrap();
+        let recorded = &last.fingerprints[&target];
+        assert!(Fingerprint::of(&target)
+            .unwrap()
+            .unwrap()
+            .same_contents(recorded));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn re_add_skips_templates_and_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rt = applied(dir.path());
+        std::fs::write(dir.path().join("home/.rendered"), "target\n").unwrap();
+        rt.re_add(true).unwrap();
+        assert_eq!(read(dir.path().join("source/rendered")), "source\n");
+        assert!(dir
+            .path()
+            .join("home/.linked")
+            .symlink_metadata()
+            .unwrap()
+            .is_symlink());
+        assert_eq!(read(dir.path().join("source/linked")), "source\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn re_add_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rt = applied(dir.path());
+        let history = dir.path().join("data").join(crate::F_HISTORY);
+        let saved = read(history.clone());
+        rt.re_add(true).unwrap();
+        assert_eq!(read(dir.path().join("source/copied")), "source\n");
+        assert_eq!(read(history), saved);
+    }
+
+    #[test]
+    fn diff_from_source_to_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        std::fs::write(&source, "a\nb\n").unwrap();
+        std::fs::write(&target, "a\nc\n").unwrap();
+        let profile = CompiledProfile {
+            source: source.clone(),
+            ty: AttrType::Copy,
+            permissions: Default::default(),
+            relative: false,
+        };
+        let diff = re_add_diff(
+            &mut crate::template::TeraRenderer::default(),
+            &target,
+            &profile,
+        )
+        .unwrap()
+        .unwrap();
+        let expected = format!(
+            "--- {}\n+++ {}\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n",
+            source.display(),
+            target.display()
+        );
+        assert_eq!(diff, expected);
+    }
+}
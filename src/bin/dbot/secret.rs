@@ -0,0 +1,346 @@
+use crate::error;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Mutex, RwLock},
+};
+use thisctx::{IntoError, WithContext};
+
+/// Name of the backend used by templates which don't specify one.
+pub const DEFAULT_BACKEND: &str = "default";
+/// Placeholder in command arguments replaced with the secret name or the
+/// encrypted file, which is appended to the command if absent.
+const PLACEHOLDER: &str = "{}";
+const MASK: &str = "********";
+/// Minimum length of lines of multi-line secrets masked on their own, since
+/// short ones like `}` would mask unrelated text.
+const MIN_FRAGMENT: usize = 6;
+
+/// Secrets revealed so far, longest first, which are masked in outputs.
+static REVEALED: Lazy<RwLock<Vec<String>>> = Lazy::new(Default::default);
+
+/// A local store of secrets, such as a password manager or the keyring.
+pub trait SecretBackend: Send + Sync {
+    fn get(&self, name: &str) -> error::Result<String>;
+}
+
+/// Reads secrets from the standard output of a command, e.g. `pass show {}`.
+pub struct CommandBackend {
+    command: Vec<String>,
+}
+
+impl CommandBackend {
+    pub fn new(command: Vec<String>) -> Self {
+        CommandBackend { command }
+    }
+}
+
+impl SecretBackend for CommandBackend {
+    fn get(&self, name: &str) -> error::Result<String> {
+        run(&self.command, name)
+    }
+}
+
+/// Decrypts secrets stored as files named after them under a directory, e.g.
+/// `age --decrypt` on `~/.secrets/github/token.age`.
+pub struct FileBackend {
+    dir: PathBuf,
+    extension: String,
+    decrypt: Vec<String>,
+}
+
+impl FileBackend {
+    pub fn new(dir: PathBuf, extension: String, decrypt: Vec<String>) -> Self {
+        FileBackend {
+            dir,
+            extension,
+            decrypt,
+        }
+    }
+}
+
+impl SecretBackend for FileBackend {
+    fn get(&self, name: &str) -> error::Result<String> {
+        // Names must not escape the directory.
+        let valid = Path::new(name)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if name.is_empty() || !valid {
+            return error::InvalidSecretName(name).fail();
+        }
+        let mut path = self.dir.join(name).into_os_string();
+        path.push(&self.extension);
+        let path = PathBuf::from(path);
+        if !path.is_file() {
+            return error::SecretNotFound(path).fail();
+        }
+        run(&self.decrypt, &path.to_string_lossy())
+    }
+}
+
+/// Runs `command` with `arg` and returns its output without the trailing
+/// newline.
+fn run(command: &[String], arg: &str) -> error::Result<String> {
+    let (program, command) = match command.split_first() {
+        Some((program, command)) => (program.as_str(), command),
+        None => ("", command),
+    };
+    let mut args = command
+        .iter()
+        .map(|s| s.replace(PLACEHOLDER, arg))
+        .collect::<Vec<_>>();
+    if !command.iter().any(|s| s.contains(PLACEHOLDER)) {
+        args.push(arg.to_owned());
+    }
+    // Inherits stdin to let backends prompt for passphrases.
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .context(error::Command(program))?;
+    if !output.status.success() {
+        return error::CommandFailed(
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end(),
+        )
+        .fail();
+    }
+    let mut secret = String::from_utf8_lossy(&output.stdout).into_owned();
+    if secret.ends_with('\n') {
+        secret.pop();
+        if secret.ends_with('\r') {
+            secret.pop();
+        }
+    }
+    Ok(secret)
+}
+
+/// Configuration of a secret backend in the config file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum BackendConfig {
+    Command {
+        command: Vec<String>,
+    },
+    File {
+        dir: PathBuf,
+        #[serde(default)]
+        extension: String,
+        decrypt: Vec<String>,
+    },
+}
+
+impl BackendConfig {
+    fn build(&self) -> Box<dyn SecretBackend> {
+        match self.clone() {
+            BackendConfig::Command { command } => Box::new(CommandBackend::new(command)),
+            BackendConfig::File {
+                dir,
+                extension,
+                decrypt,
+            } => Box::new(FileBackend::new(dir, extension, decrypt)),
+        }
+    }
+}
+
+/// Named secret backends, where each secret is retrieved at most once.
+#[derive(Default)]
+pub struct Secrets {
+    backends: BTreeMap<String, Box<dyn SecretBackend>>,
+    cache: Mutex<HashMap<(String, String), String>>,
+}
+
+impl Secrets {
+    pub fn new(configs: &BTreeMap<String, BackendConfig>) -> Self {
+        let mut secrets = Secrets::default();
+        for (name, config) in configs {
+            secrets.add_backend(name, config.build());
+        }
+        secrets
+    }
+
+    pub fn add_backend(&mut self, name: impl Into<String>, backend: Box<dyn SecretBackend>) {
+        self.backends.insert(name.into(), backend);
+    }
+
+    /// Retrieves `name` from `backend`, which defaults to the one named
+    /// [`DEFAULT_BACKEND`] or the only configured one.
+    pub fn get(&self, backend: Option<&str>, name: &str) -> error::Result<String> {
+        let backend = match backend {
+            Some(backend) => backend,
+            None => self
+                .default_backend()
+                .context(error::NoSecretBackend(name))?,
+        };
+        let key = (backend.to_owned(), name.to_owned());
+        if let Some(secret) = self.cache.lock().unwrap().get(&key) {
+            return Ok(secret.clone());
+        }
+        let secret = self
+            .backends
+            .get(backend)
+            .context(error::UnknownSecretBackend(backend))?
+            .get(name)?;
+        reveal(&secret);
+        self.cache.lock().unwrap().insert(key, secret.clone());
+        Ok(secret)
+    }
+
+    fn default_backend(&self) -> Option<&str> {
+        if self.backends.contains_key(DEFAULT_BACKEND) {
+            return Some(DEFAULT_BACKEND);
+        }
+        match self.backends.keys().collect::<Vec<_>>()[..] {
+            [only] => Some(only),
+            _ => None,
+        }
+    }
+}
+
+/// Remembers `secret` to mask it, including long enough lines of multi-line
+/// ones.
+fn reveal(secret: &str) {
+    let mut revealed = REVEALED.write().unwrap();
+    let fragments = secret
+        .lines()
+        .map(str::trim)
+        .filter(|s| s.chars().count() >= MIN_FRAGMENT);
+    for s in std::iter::once(secret.trim()).chain(fragments) {
+        if !s.is_empty() && !revealed.iter().any(|r| r == s) {
+            revealed.push(s.to_owned());
+        }
+    }
+    revealed.sort_by_key(|s| std::cmp::Reverse(s.len()));
+}
+
+/// Replaces revealed secrets in `s` with asterisks.
+pub fn mask(s: &str) -> Cow<'_, str> {
+    let revealed = REVEALED.read().unwrap();
+    let mut s = Cow::Borrowed(s);
+    for secret in revealed.iter() {
+        if s.contains(secret.as_str()) {
+            s = Cow::Owned(s.replace(secret.as_str(), MASK));
+        }
+    }
+    s
+}
+
+/// Standard output with revealed secrets masked, used to write logs.
+pub struct MaskedStdout;
+
+impl Write for MaskedStdout {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let s = String::from_utf8_lossy(buf);
+        std::io::stdout().write_all(mask(&s).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stdout().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Returns secrets derived from their names and counts retrievals.
+    #[derive(Default)]
+    struct FakeBackend(Arc<AtomicUsize>);
+
+    impl SecretBackend for FakeBackend {
+        fn get(&self, name: &str) -> error::Result<String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(format!("fake-{}", name.replace('/', "-")))
+        }
+    }
+
+    #[test]
+    fn cached_secrets() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut secrets = Secrets::default();
+        secrets.add_backend("fake", Box::new(FakeBackend(calls.clone())));
+        assert_eq!(
+            secrets.get(None, "github/token").unwrap(),
+            "fake-github-token"
+        );
+        assert_eq!(
+            secrets.get(Some("fake"), "github/token").unwrap(),
+            "fake-github-token"
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            secrets.get(Some("pass"), "github/token"),
+            Err(error::Error::UnknownSecretBackend(_))
+        ));
+        secrets.add_backend("other", Box::<FakeBackend>::default());
+        assert!(matches!(
+            secrets.get(None, "npm/token"),
+            Err(error::Error::NoSecretBackend(_))
+        ));
+    }
+
+    #[test]
+    fn masked_secrets() {
+        let mut secrets = Secrets::default();
+        secrets.add_backend("fake", Box::<FakeBackend>::default());
+        assert_eq!(mask("token=fake-masked"), "token=fake-masked");
+        secrets.get(None, "masked").unwrap();
+        assert_eq!(mask("token=fake-masked"), "token=********");
+    }
+
+    #[test]
+    fn masked_multiline_secrets() {
+        let key = "-----BEGIN KEY-----\nmultiline-secret\n}\n-----END KEY-----";
+        reveal(key);
+        assert_eq!(mask(&format!("key: {key}")), "key: ********");
+        assert_eq!(mask("  multiline-secret\n"), "  ********\n");
+        assert_eq!(mask("fn main() {}"), "fn main() {}");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn command_backends() {
+        let command = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let backend = CommandBackend::new(command(&["sh", "-c", "echo \"pass:$0\"", "{}"]));
+        assert_eq!(backend.get("github/token").unwrap(), "pass:github/token");
+        let backend = CommandBackend::new(command(&["sh", "-c", "echo oops >&2; exit 1"]));
+        assert!(matches!(
+            backend.get("github/token"),
+            Err(error::Error::CommandFailed(_, _, stderr)) if stderr == "oops"
+        ));
+
+        // Uses `cat` to decrypt files in tests.
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("github")).unwrap();
+        std::fs::write(dir.path().join("github/token.age"), "decrypted\n").unwrap();
+        let backend = FileBackend::new(dir.path().to_owned(), ".age".to_owned(), command(&["cat"]));
+        assert_eq!(backend.get("github/token").unwrap(), "decrypted");
+        assert!(matches!(
+            backend.get("npm/token"),
+            Err(error::Error::SecretNotFound(_))
+        ));
+        std::fs::write(dir.path().join("outside.age"), "outside\n").unwrap();
+        let backend = FileBackend::new(
+            dir.path().join("github"),
+            ".age".to_owned(),
+            command(&["cat"]),
+        );
+        for name in ["../outside", "/etc/passwd", ""] {
+            assert!(
+                matches!(backend.get(name), Err(error::Error::InvalidSecretName(_))),
+                "{name}"
+            );
+        }
+    }
+}
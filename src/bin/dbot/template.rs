@@ -1,39 +1,186 @@
-use dbot::TemplateRenderer;
+mod functions;
+
+use crate::{error, secret::Secrets};
+use dbot::{profile::Host, RenderContext, TemplateRenderer};
 use serde::Serialize;
-use tera::{Context, Tera};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tera::{to_value, Context, Tera, Value};
+use thisctx::WithContext;
+
+/// Key of built-in variables in the template context.
+pub const K_BUILTINS: &str = "dbot";
+
+/// Variables about the current machine available to templates as `dbot`.
+#[derive(Debug, Serialize)]
+pub struct Builtins {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+    pub user: String,
+    pub home: PathBuf,
+    pub source_dir: PathBuf,
+    pub target_dir: PathBuf,
+    pub env: BTreeMap<String, String>,
+}
+
+impl Builtins {
+    pub fn new(host: Host, home: &Path, source_dir: &Path, target_dir: &Path) -> Self {
+        Builtins {
+            hostname: host.hostname,
+            os: host.os,
+            arch: host.arch,
+            user: host.user,
+            home: home.to_owned(),
+            source_dir: source_dir.to_owned(),
+            target_dir: target_dir.to_owned(),
+            env: host.env.into_iter().collect(),
+        }
+    }
+}
 
-#[derive(Default)]
 pub struct TeraRenderer {
     tera: Tera,
     context: Context,
+    /// Contents of sources registered as templates, which are parsed again
+    /// only if changed.
+    sources: HashMap<PathBuf, String>,
+}
+
+impl Default for TeraRenderer {
+    fn default() -> Self {
+        let mut tera = Tera::default();
+        // Templates are named after their sources, which must never be
+        // escaped whatever their extensions are.
+        tera.autoescape_on(Vec::new());
+        TeraRenderer {
+            tera,
+            context: Context::default(),
+            sources: HashMap::new(),
+        }
+    }
 }
 
 impl TemplateRenderer for TeraRenderer {
     type Err = tera::Error;
 
-    fn render(&mut self, s: &str) -> Result<String, Self::Err> {
-        self.tera.render_str(s, &self.context)
+    fn render(&mut self, s: &str, context: &RenderContext) -> Result<String, Self::Err> {
+        // Names the template after its source to locate errors.
+        let name = context.source.to_string_lossy();
+        if self.sources.get(context.source).map(String::as_str) != Some(s) {
+            self.tera.add_raw_template(&name, s)?;
+            self.sources.insert(context.source.to_owned(), s.to_owned());
+        }
+        let mut builtins = self
+            .context
+            .get(K_BUILTINS)
+            .cloned()
+            .unwrap_or_else(|| Value::Object(Default::default()));
+        if let Value::Object(builtins) = &mut builtins {
+            builtins.insert("target".to_owned(), to_value(context.target)?);
+            builtins.insert("source".to_owned(), to_value(context.source)?);
+        }
+        let mut tera_context = self.context.clone();
+        tera_context.insert(K_BUILTINS, &builtins);
+        self.tera.render(&name, &tera_context)
     }
 }
 
 impl TeraRenderer {
+    /// Registers files under `dir` as templates named after their paths
+    /// relative to `dir`, which can be included or imported by others.
+    pub fn add_templates(&mut self, dir: &Path) -> error::Result<()> {
+        if !dir.is_dir() {
+            return Ok(());
+        }
+        let mut templates = Vec::new();
+        let mut dirs = vec![dir.to_owned()];
+        while let Some(current) = dirs.pop() {
+            for entry in std::fs::read_dir(&current).context(error::Io(&current))? {
+                let path = entry.context(error::Io(&current))?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let content = std::fs::read_to_string(&path).context(error::Io(&path))?;
+                let name = path
+                    .strip_prefix(dir)
+                    .unwrap()
+                    .components()
+                    .map(|compo| compo.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                templates.push((name, content));
+            }
+        }
+        self.tera
+            .add_raw_templates(templates)
+            .context(error::Templates(dir))
+    }
+
+    /// Registers functions available to templates, which resolve relative
+    /// paths against the source or target directory.
+    pub fn register_functions(&mut self, home: &Path, source_dir: &Path, target_dir: &Path) {
+        functions::register(
+            &mut self.tera,
+            functions::Dirs {
+                home: home.to_owned(),
+                source: source_dir.to_owned(),
+                target: target_dir.to_owned(),
+            },
+        );
+    }
+
+    /// Registers the `secret` function retrieving values from `secrets`.
+    pub fn register_secrets(&mut self, secrets: Arc<Secrets>) {
+        functions::register_secrets(&mut self.tera, secrets);
+    }
+
     pub fn add_data<T>(&mut self, key: impl Into<String>, val: &T)
     where
         T: ?Sized + Serialize,
     {
         self.context.insert(key, val);
     }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dbot::{compile::CompiledProfile, profile::AttrType};
     use std::{io::Write, path::Path};
     use tempfile::NamedTempFile;
 
+    fn template_profile(source: &Path) -> CompiledProfile {
+        CompiledProfile {
+            source: source.to_owned(),
+            ty: AttrType::Template,
+            permissions: Default::default(),
+            relative: false,
+        }
+    }
+
+    fn render_str(renderer: &mut TeraRenderer, s: &str) -> Result<String, tera::Error> {
+        let profile = template_profile("/src/config.html".as_ref());
+        renderer.render(s, &RenderContext::new("/dst/.config".as_ref(), &profile))
+    }
+
     fn render_path(renderer: &mut TeraRenderer, path: &Path) -> String {
         let content = std::fs::read_to_string(path).unwrap();
-        renderer.render(&content).unwrap()
+        let profile = template_profile(path);
+        renderer
+            .render(
+                &content,
+                &RenderContext::new("/dst/file".as_ref(), &profile),
+            )
+            .unwrap()
     }
 
     #[test]
@@ -55,4 +202,82 @@ mod tests {
             .unwrap();
         assert_eq!(render_path(&mut render, tempfile.path()), "Hello, DBot!");
     }
+
+    #[test]
+    fn render_builtins() {
+        let host = Host {
+            os: "linux".to_owned(),
+            hostname: "work-laptop".to_owned(),
+            env: [("EDITOR".to_owned(), "nvim".to_owned())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let mut render = TeraRenderer::default();
+        render.add_data(
+            "dbot",
+            &Builtins::new(
+                host,
+                "/home/dbot".as_ref(),
+                "/src".as_ref(),
+                "/dst".as_ref(),
+            ),
+        );
+        let template = r#"{% if dbot.os == "linux" %}{{ dbot.hostname }}{% endif %} {{ dbot.env.EDITOR }} {{ dbot.home }}"#;
+        assert_eq!(
+            render_str(&mut render, template).unwrap(),
+            "work-laptop nvim /home/dbot"
+        );
+    }
+
+    #[test]
+    fn render_context() {
+        let mut render = TeraRenderer::default();
+        assert_eq!(
+            render_str(&mut render, "{{ dbot.target }} <{{ dbot.source }}>").unwrap(),
+            "/dst/.config </src/config.html>"
+        );
+        let err = render_str(&mut render, "{{ undefined }}").unwrap_err();
+        assert!(err.to_string().contains("/src/config.html"), "{err}");
+    }
+
+    #[test]
+    fn render_registered_source() {
+        let mut render = TeraRenderer::default();
+        let profile = template_profile("/src/config".as_ref());
+        for target in ["/dst/a", "/dst/b"] {
+            let context = RenderContext::new(target.as_ref(), &profile);
+            assert_eq!(
+                render.render("{{ dbot.target }}", &context).unwrap(),
+                target
+            );
+        }
+        assert_eq!(render.sources.len(), 1);
+        // Changed contents of the same source are parsed again.
+        let context = RenderContext::new("/dst/a".as_ref(), &profile);
+        assert_eq!(
+            render.render("{{ dbot.source }}", &context).unwrap(),
+            "/src/config"
+        );
+    }
+
+    #[test]
+    fn render_shared_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("macros")).unwrap();
+        std::fs::write(dir.path().join("palette"), "fg={{ data.fg }}").unwrap();
+        std::fs::write(
+            dir.path().join("macros/colors"),
+            "{% macro color(name) %}#{{ name }}{% endmacro color %}",
+        )
+        .unwrap();
+        let mut render = TeraRenderer::default();
+        render.add_templates(dir.path()).unwrap();
+        render.add_data(
+            "data",
+            &[("fg", "fff")].into_iter().collect::<BTreeMap<_, _>>(),
+        );
+        let template = r#"{% import "macros/colors" as colors %}{% include "palette" %} {{ colors::color(name="000") }}"#;
+        assert_eq!(render_str(&mut render, template).unwrap(), "fg=fff #000");
+    }
 }
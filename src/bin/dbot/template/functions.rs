@@ -0,0 +1,280 @@
+use crate::secret::Secrets;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+};
+use tera::{from_value, to_value, Error, Result, Tera, Value};
+
+/// Directories which paths passed to functions are resolved against.
+#[derive(Clone, Debug)]
+pub struct Dirs {
+    pub home: PathBuf,
+    pub source: PathBuf,
+    pub target: PathBuf,
+}
+
+impl Dirs {
+    /// Resolves `path` against `base`, where a leading `~` means the home
+    /// directory.
+    fn resolve(&self, base: &Path, path: &str) -> PathBuf {
+        match Path::new(path).strip_prefix("~") {
+            Ok(rest) => self.home.join(rest),
+            Err(_) => base.join(path),
+        }
+    }
+}
+
+fn arg<T>(args: &HashMap<String, Value>, fn_name: &str, name: &str) -> Result<Option<T>>
+where
+    T: serde::de::DeserializeOwned,
+{
+    args.get(name)
+        .map(|val| {
+            from_value(val.clone()).map_err(|_| {
+                Error::msg(format!(
+                    "Function `{fn_name}` received an invalid `{name}`: {val}"
+                ))
+            })
+        })
+        .transpose()
+}
+
+fn required_arg<T>(args: &HashMap<String, Value>, fn_name: &str, name: &str) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    arg(args, fn_name, name)?.ok_or_else(|| {
+        Error::msg(format!(
+            "Function `{fn_name}` was called without a `{name}` argument"
+        ))
+    })
+}
+
+fn path_value(path: &Path) -> Result<Value> {
+    Ok(to_value(path)?)
+}
+
+/// Searches `name` in directories of the `PATH` variable like `which`.
+fn lookup_path(name: &str) -> Option<PathBuf> {
+    if name.contains(std::path::MAIN_SEPARATOR) {
+        return Path::new(name).is_file().then(|| name.into());
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(path))
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        path.metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+/// Outputs of commands, which are cached since the same command is likely
+/// called by many templates.
+type OutputCache = Arc<Mutex<HashMap<(String, Vec<String>), String>>>;
+
+fn output(cache: &OutputCache, cmd: String, args: Vec<String>) -> Result<String> {
+    let key = (cmd, args);
+    if let Some(stdout) = cache.lock().unwrap().get(&key) {
+        return Ok(stdout.clone());
+    }
+    let (cmd, args) = &key;
+    let output = Command::new(cmd)
+        .args(args)
+        .output()
+        .map_err(|e| Error::chain(format!("Failed to run `{cmd}`"), e))?;
+    if !output.status.success() {
+        return Err(Error::msg(format!(
+            "Command `{cmd}` failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )));
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|e| Error::chain(format!("Output of `{cmd}` is not UTF-8"), e))?;
+    cache.lock().unwrap().insert(key, stdout.clone());
+    Ok(stdout)
+}
+
+/// Registers functions available to all templates, where relative paths are
+/// resolved against the source directory for `include_file` and against the
+/// target directory for `exists`.
+pub fn register(tera: &mut Tera, dirs: Dirs) {
+    let dirs = Arc::new(dirs);
+
+    let d = dirs.clone();
+    tera.register_function("include_file", move |args: &HashMap<String, Value>| {
+        let path = required_arg::<String>(args, "include_file", "path")?;
+        let path = d.resolve(&d.source, &path);
+        std::fs::read_to_string(&path)
+            .map(Value::String)
+            .map_err(|e| Error::chain(format!("Failed to read '{}'", path.display()), e))
+    });
+
+    let cache = OutputCache::default();
+    tera.register_function("output", move |args: &HashMap<String, Value>| {
+        let cmd = required_arg(args, "output", "cmd")?;
+        let cmd_args = arg(args, "output", "args")?.unwrap_or_default();
+        output(&cache, cmd, cmd_args).map(Value::String)
+    });
+
+    tera.register_function("env", |args: &HashMap<String, Value>| {
+        let name = required_arg::<String>(args, "env", "name")?;
+        match (std::env::var(&name), args.get("default")) {
+            (Ok(val), _) => Ok(Value::String(val)),
+            (Err(_), Some(default)) => Ok(default.clone()),
+            (Err(_), None) => Err(Error::msg(format!(
+                "Environment variable `{name}` is not set and no default is given"
+            ))),
+        }
+    });
+
+    let d = dirs;
+    tera.register_function("exists", move |args: &HashMap<String, Value>| {
+        let path = required_arg::<String>(args, "exists", "path")?;
+        let path = d.resolve(&d.target, &path);
+        Ok(Value::Bool(path.symlink_metadata().is_ok()))
+    });
+
+    tera.register_function("lookup_path", |args: &HashMap<String, Value>| {
+        let name = required_arg::<String>(args, "lookup_path", "name")?;
+        // Returns an empty string, which is falsy, if not found.
+        match lookup_path(&name) {
+            Some(path) => path_value(&path),
+            None => Ok(Value::String(String::new())),
+        }
+    });
+
+    tera.register_function("join_path", |args: &HashMap<String, Value>| {
+        let parts = required_arg::<Vec<String>>(args, "join_path", "parts")?;
+        path_value(&parts.iter().collect::<PathBuf>())
+    });
+}
+
+/// Registers the `secret` function, whose results are cached by `secrets`
+/// and never stored in the template context.
+pub fn register_secrets(tera: &mut Tera, secrets: Arc<Secrets>) {
+    tera.register_function("secret", move |args: &HashMap<String, Value>| {
+        let name = required_arg::<String>(args, "secret", "name")?;
+        let backend = arg::<String>(args, "secret", "backend")?;
+        secrets
+            .get(backend.as_deref(), &name)
+            .map(Value::String)
+            .map_err(|e| Error::chain(format!("Failed to get secret '{name}'"), e))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::CommandBackend;
+    use tera::Context;
+
+    fn tera(dirs: Dirs) -> Tera {
+        let mut tera = Tera::default();
+        register(&mut tera, dirs);
+        tera
+    }
+
+    fn render(tera: &mut Tera, s: &str) -> Result<String> {
+        tera.render_str(s, &Context::new())
+    }
+
+    #[test]
+    fn path_functions() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("snippet"), "included").unwrap();
+        std::fs::write(target.path().join("installed"), "").unwrap();
+        let mut tera = tera(Dirs {
+            home: target.path().to_owned(),
+            source: source.path().to_owned(),
+            target: target.path().to_owned(),
+        });
+        let template = r#"{{ include_file(path="snippet") }} {{ exists(path="installed") }} {{ exists(path="~/installed") }} {{ exists(path="missing") }} {{ join_path(parts=["a", "b"]) }}"#;
+        assert_eq!(
+            render(&mut tera, template).unwrap(),
+            "included true true false a/b"
+        );
+        assert!(render(&mut tera, r#"{{ include_file(path="missing") }}"#).is_err());
+        assert_eq!(
+            render(&mut tera, r#"{{ lookup_path(name="sh") != "" }}"#).unwrap(),
+            "true"
+        );
+        assert_eq!(
+            render(&mut tera, r#"{{ lookup_path(name="no-such-command") }}"#).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn env_function() {
+        let mut tera = tera(Dirs {
+            home: "".into(),
+            source: "".into(),
+            target: "".into(),
+        });
+        assert_eq!(
+            render(&mut tera, r#"{{ env(name="PATH") != "" }}"#).unwrap(),
+            "true"
+        );
+        assert_eq!(
+            render(
+                &mut tera,
+                r#"{{ env(name="DBOT_NO_SUCH_VAR", default="x") }}"#
+            )
+            .unwrap(),
+            "x"
+        );
+        assert!(render(&mut tera, r#"{{ env(name="DBOT_NO_SUCH_VAR") }}"#).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn cached_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tera = tera(Dirs {
+            home: "".into(),
+            source: "".into(),
+            target: "".into(),
+        });
+        // Counts how many times the command runs.
+        let count = dir.path().join("count");
+        let script = format!("echo >> '{0}'; wc -l < '{0}'", count.display());
+        let template = format!(r#"{{{{ output(cmd="sh", args=["-c", "{script}"]) | trim }}}}"#);
+        assert_eq!(render(&mut tera, &template).unwrap(), "1");
+        assert_eq!(render(&mut tera, &template).unwrap(), "1");
+        assert!(render(&mut tera, r#"{{ output(cmd="false") }}"#).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn secret_function() {
+        let mut secrets = Secrets::default();
+        secrets.add_backend(
+            "fake",
+            Box::new(CommandBackend::new(vec![
+                "echo".to_owned(),
+                "token-{}".to_owned(),
+            ])),
+        );
+        let mut tera = Tera::default();
+        register_secrets(&mut tera, Arc::new(secrets));
+        assert_eq!(
+            render(&mut tera, r#"{{ secret(name="github/token") }}"#).unwrap(),
+            "token-github/token"
+        );
+        assert!(render(&mut tera, r#"{{ secret(name="a", backend="pass") }}"#).is_err());
+    }
+}
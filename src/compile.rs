@@ -1,74 +1,305 @@
 use crate::{
-    error,
-    profile::{AttrType, ProfileAttr, ProfileEntries},
+    error::{self, Diagnostics, Span},
+    profile::{AttrType, Permissions, ProfileAttr, ProfileEntries},
 };
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    path::{Path, PathBuf},
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Component, Path, PathBuf},
 };
 use thisctx::{IntoError, WithContext};
+use tracing::warn;
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct CompiledProfile {
     pub source: PathBuf,
     pub ty: AttrType,
+    #[serde(default, skip_serializing_if = "Permissions::is_default")]
+    pub permissions: Permissions,
+    /// Whether the symlink points to a path relative to its parent.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub relative: bool,
+}
+
+impl CompiledProfile {
+    /// Returns the path that a symlink at `target` should point to.
+    pub fn link_original(&self, target: &Path) -> PathBuf {
+        match target.parent() {
+            Some(parent) if self.relative => relative_path(parent, &self.source),
+            _ => self.source.clone(),
+        }
+    }
+}
+
+/// Returns a path pointing to `path` from the directory `base`, both of which
+/// should be absolute and normalized.
+fn relative_path(base: &Path, path: &Path) -> PathBuf {
+    let mut base = base.components().peekable();
+    let mut path = path.components().peekable();
+    while let (Some(a), Some(b)) = (base.peek(), path.peek()) {
+        if a != b {
+            break;
+        }
+        base.next();
+        path.next();
+    }
+    let mut relative = base.map(|_| Component::ParentDir).collect::<PathBuf>();
+    relative.extend(path);
+    if relative.as_os_str().is_empty() {
+        relative.push(Component::CurDir);
+    }
+    relative
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct CompilerOptions<'a> {
     pub source: &'a Path,
     pub target: &'a Path,
+    /// Creates relative symlinks unless specified by `+relative`.
+    pub relative_links: bool,
+    /// Lets later profile entries override earlier ones which produce the
+    /// same target from a different source, instead of failing.
+    pub later_wins: bool,
+    /// Sources under these directories are never deployed, e.g. those only
+    /// used by other templates.
+    pub excluded: Vec<PathBuf>,
+}
+
+/// A profile entry which produces a target.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Origin {
+    /// Path of the node in the profile.
+    pub profile: PathBuf,
+    pub source: PathBuf,
+    pub ty: AttrType,
+    /// Where the source is defined, if known.
+    pub span: Option<Span>,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "'{}' ({} '{}')",
+            self.profile.display(),
+            self.ty,
+            self.source.display()
+        )?;
+        if let Some(Span { file, location }) = &self.span {
+            write!(
+                f,
+                " in '{}:{}:{}'",
+                file.display(),
+                location.line,
+                location.column
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A target produced by two profile entries with different sources.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Overlap {
+    pub target: PathBuf,
+    pub earlier: Origin,
+    /// The entry which is defined later in the profile and takes precedence
+    /// if overriding is allowed.
+    pub later: Origin,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "'{}' is produced by both {} and {}",
+            self.target.display(),
+            self.earlier,
+            self.later
+        )
+    }
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Overlaps(pub Vec<Overlap>);
+
+impl fmt::Display for Overlaps {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, overlap) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "  {overlap}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compiled entries sorted by their targets, where parents always come
+/// before their children.
+#[derive(Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(transparent)]
-pub struct CompiledEntries(pub(crate) HashMap<PathBuf, CompiledProfile>);
+pub struct CompiledEntries(pub(crate) BTreeMap<PathBuf, CompiledProfile>);
 
 impl CompiledEntries {
-    pub fn iter(&self) -> impl Iterator<Item = (&Path, &CompiledProfile)> {
+    /// Iterates entries with parents before children, in which targets are
+    /// created.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Path, &CompiledProfile)> {
         self.0
             .iter()
             .map(|(path, entries)| (path.as_path(), entries))
     }
+
+    /// Iterates entries with children before parents, in which targets are
+    /// removed.
+    pub fn iter_rev(&self) -> impl Iterator<Item = (&Path, &CompiledProfile)> {
+        self.iter().rev()
+    }
+
+    pub fn get(&self, target: &Path) -> Option<&CompiledProfile> {
+        self.0.get(target)
+    }
+
+    pub fn insert(&mut self, target: PathBuf, profile: CompiledProfile) -> Option<CompiledProfile> {
+        self.0.insert(target, profile)
+    }
+
+    pub fn remove(&mut self, target: &Path) -> Option<CompiledProfile> {
+        self.0.remove(target)
+    }
 }
 
 pub fn compile(
     options: &CompilerOptions,
     entries: ProfileEntries,
 ) -> error::Result<CompiledEntries> {
-    let mut compiled = CompiledEntries(<_>::default());
+    let mut diagnostics = Diagnostics::default();
+    let compiled = compile_all(options, entries, &mut diagnostics);
+    diagnostics.into_result(compiled)
+}
+
+/// Compiles all entries, where invalid entries are skipped and their errors
+/// are recorded in `diagnostics`.
+pub fn compile_all(
+    options: &CompilerOptions,
+    entries: ProfileEntries,
+    diagnostics: &mut Diagnostics,
+) -> CompiledEntries {
+    let mut state = CompileState {
+        excluded: &options.excluded,
+        ..Default::default()
+    };
     // Compile child targets first to avoid double compiling.
     for (target, attr) in entries.0.into_iter().rev() {
         if cfg!(not(unix)) && matches!(attr.ty, AttrType::Link) {
-            return error::UnsupportedSymlinks(attr.source).fail();
+            diagnostics.push(error::Error::UnsupportedSymlinks(attr.source));
+            continue;
+        }
+        let source = options.source.join(&attr.source);
+        if state.is_excluded(&source) {
+            warn!("Skip excluded source '{}'", source.display());
+            continue;
+        }
+        // Sources replaced by others of the same node when merging profiles.
+        for overridden in attr.overridden.iter() {
+            state.overlaps.push(Overlap {
+                target: options.target.join(&target),
+                earlier: Origin {
+                    profile: target.clone(),
+                    source: options.source.join(&overridden.source),
+                    ty: overridden.ty.unwrap_or(attr.ty),
+                    span: overridden.origins.first().and_then(|origin| origin.span()),
+                },
+                later: Origin {
+                    profile: target.clone(),
+                    source: source.clone(),
+                    ty: attr.ty,
+                    span: attr.origins.span("source"),
+                },
+            });
         }
-        compile_entry(
+        let result = compile_entry(
             options.target.join(&target),
-            options.source.join(&attr.source),
+            source,
+            &target,
             &attr,
             // TODO: copied source must be recursive
             attr.recursive || matches!(attr.ty, AttrType::Copy),
-            &mut compiled,
-        )?;
+            attr.ty == AttrType::Link && attr.relative.unwrap_or(options.relative_links),
+            &mut state,
+        );
+        diagnostics.record(result);
+    }
+    if !options.later_wins && !state.overlaps.is_empty() {
+        let mut overlaps = state.overlaps;
+        overlaps.sort_by(|a, b| a.target.cmp(&b.target));
+        diagnostics.push(error::Error::OverlappingTargets(Overlaps(overlaps)));
+    }
+    state.compiled
+}
+
+#[derive(Default)]
+struct CompileState<'a> {
+    compiled: CompiledEntries,
+    /// Profile paths of the entries which produce compiled targets and where
+    /// their sources are defined.
+    origins: HashMap<PathBuf, (PathBuf, Option<Span>)>,
+    overlaps: Vec<Overlap>,
+    excluded: &'a [PathBuf],
+}
+
+impl CompileState<'_> {
+    fn is_excluded(&self, source: &Path) -> bool {
+        self.excluded.iter().any(|dir| source.starts_with(dir))
     }
-    Ok(compiled)
 }
 
 fn compile_entry(
     target: PathBuf,
     mut source: PathBuf,
+    profile: &Path,
     attr: &ProfileAttr,
     recursive: bool,
-    compiled: &mut CompiledEntries,
+    relative: bool,
+    state: &mut CompileState,
 ) -> error::Result<()> {
-    // 1) Check whether compiled.
-    if compiled.0.contains_key(&target) {
+    // 1) Check whether compiled, which is fine if a later entry merely
+    // overrides attributes of the same source, or if it is a child node
+    // narrowing its parent, e.g. a file with its own source under a recursive
+    // copy.
+    if let Some(compiled) = state.compiled.0.get(&target) {
+        let (later, span) = &state.origins[&target];
+        let nested = later != profile && later.starts_with(profile);
+        if compiled.source != source && !nested {
+            state.overlaps.push(Overlap {
+                earlier: Origin {
+                    profile: profile.to_owned(),
+                    source,
+                    ty: attr.ty,
+                    span: attr.origins.span("source"),
+                },
+                later: Origin {
+                    profile: later.clone(),
+                    source: compiled.source.clone(),
+                    ty: compiled.ty,
+                    span: span.clone(),
+                },
+                target,
+            });
+        }
         return Ok(());
     }
 
     // 2) Resolve symlink.
-    let metadata = source.metadata().context(error::IoFailed(&source))?;
+    let metadata = match source.metadata() {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let span = attr.origins.span("source");
+            return error::MissingSource(source, span).fail();
+        }
+        Err(e) => return Err(e).context(error::IoFailed(&source)),
+    };
     if metadata.is_symlink() {
         source = std::fs::read_link(&source).context(error::IoFailed(&source))?;
     }
@@ -79,29 +310,38 @@ fn compile_entry(
             for entry in std::fs::read_dir(&source).context(error::IoFailed(&source))? {
                 let entry = entry.context(error::IoFailed(&source))?;
                 let filename = entry.file_name();
-                if attr.ignore.is_match(&filename) {
+                if attr.ignore.is_match(&filename) || state.is_excluded(&entry.path()) {
                     continue;
                 }
                 compile_entry(
                     target.join(&filename),
                     source.join(&filename),
+                    profile,
                     attr,
                     recursive,
-                    compiled,
+                    relative,
+                    state,
                 )?;
             }
             return Ok(());
         } else if attr.ty == AttrType::Template {
-            return error::UnexpectedDirectoryForTemplate(source).fail();
+            let span = attr.origins.span("type");
+            return error::UnexpectedDirectoryForTemplate(source, span).fail();
         }
     }
 
     // 4) Insert current source.
-    compiled.0.insert(
+    state.origins.insert(
+        target.clone(),
+        (profile.to_owned(), attr.origins.span("source")),
+    );
+    state.compiled.0.insert(
         target,
         CompiledProfile {
             source,
             ty: attr.ty,
+            permissions: attr.permissions,
+            relative,
         },
     );
     Ok(())
@@ -110,15 +350,23 @@ fn compile_entry(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::profile::Profile;
+    use crate::{profile::Profile, utils::compiler_options, Merge};
     use std::path::Path;
 
     fn compile_str(source: &Path, profile: &str) -> error::Result<CompiledEntries> {
+        compile_with(source, profile, false)
+    }
+
+    fn compile_with(
+        source: &Path,
+        profile: &str,
+        later_wins: bool,
+    ) -> error::Result<CompiledEntries> {
         let profile = serde_yaml::from_str::<Profile>(profile).unwrap();
         compile(
             &CompilerOptions {
-                source,
-                target: "~".as_ref(),
+                later_wins,
+                ..compiler_options(source, "~".as_ref())
             },
             profile.into_entries().unwrap(),
         )
@@ -151,6 +399,8 @@ mod tests {
                         CompiledProfile {
                             source: tmp.join("path/to/source").join(filename),
                             ty,
+                            permissions: <_>::default(),
+                            relative: false,
                         },
                     )
                 })
@@ -181,8 +431,8 @@ mod tests {
             tempdir.path(),
             r#"
             path/to/target:
-              ~source: path/to/source
-              ~type: link
+              +source: path/to/source
+              +type: link
             "#,
         )
         .unwrap();
@@ -192,6 +442,8 @@ mod tests {
                 CompiledProfile {
                     source: tempdir.path().join("path/to/source"),
                     ty: AttrType::Link,
+                    permissions: <_>::default(),
+                    relative: false,
                 },
             ))
             .collect(),
@@ -207,9 +459,9 @@ mod tests {
             tempdir.path(),
             r#"
             path/to/target:
-              ~source: path/to/source
-              ~type: link
-              ~recursive: true
+              +source: path/to/source
+              +type: link
+              +recursive: true
             "#,
         )
         .unwrap();
@@ -225,14 +477,14 @@ mod tests {
             tempdir.path(),
             r#"
             path/to/target:
-              ~source: path/to/source
-              ~type: template
+              +source: path/to/source
+              +type: template
             "#,
         );
         assert!(
             matches!(
                 &result,
-                Err(error::Error::UnexpectedDirectoryForTemplate(path))
+                Err(error::Error::UnexpectedDirectoryForTemplate(path, _))
                     if path == &tempdir.path().join("path/to/source")
             ),
             "{result:?}"
@@ -247,9 +499,9 @@ mod tests {
             tempdir.path(),
             r#"
             path/to/target:
-              ~source: path/to/source
-              ~type: template
-              ~recursive: true
+              +source: path/to/source
+              +type: template
+              +recursive: true
             "#,
         )
         .unwrap();
@@ -280,8 +532,8 @@ mod tests {
             tempdir.path(),
             r#"
             path/to/target:
-                ~source: path/to/source
-                ~ignore:
+                +source: path/to/source
+                +ignore:
                   - ignore*
                   - ignore_dir/*
             "#,
@@ -314,11 +566,11 @@ mod tests {
             tempdir.path(),
             r#"
             path/to/target:
-              ~source: path/to/source
-              ~type: link
-              ~recursive: true
+              +source: path/to/source
+              +type: link
+              +recursive: true
               dir2:
-                ~recursive: false
+                +recursive: false
             "#,
         )
         .unwrap();
@@ -329,4 +581,197 @@ mod tests {
         );
         assert_eq!(entries, expected);
     }
+
+    #[test]
+    fn relative_link_path() {
+        assert_eq!(
+            relative_path(
+                "/home/user/.config".as_ref(),
+                "/home/user/dotfiles/nvim".as_ref()
+            ),
+            Path::new("../dotfiles/nvim")
+        );
+        assert_eq!(
+            relative_path("/home/user".as_ref(), "/home/user".as_ref()),
+            Path::new(".")
+        );
+    }
+
+    #[test]
+    fn nested_overrides() {
+        let tempdir = tempfile::tempdir().unwrap();
+        create_tree!(tempdir.path(), {
+            path: {
+                to: {
+                    source: {
+                        file1,
+                        file2,
+                        dir: {
+                            file3,
+                        },
+                    },
+                    other: {
+                        file3,
+                    },
+                },
+            },
+        });
+        // Children narrow the recursive copy of their parent, which never
+        // overlaps.
+        let entries = compile_str(
+            tempdir.path(),
+            r#"
+            path/to/target:
+              +source: path/to/source
+              file1:
+                +type: template
+              file2: path/to/other/file3
+              dir:
+                +source: path/to/other
+                +type: link
+            "#,
+        )
+        .unwrap();
+        let get = |target: &str| &entries.0[Path::new("~/path/to/target").join(target).as_path()];
+        assert_eq!(get("file1").ty, AttrType::Template);
+        assert_eq!(
+            get("file2").source,
+            tempdir.path().join("path/to/other/file3")
+        );
+        assert_eq!(get("dir").ty, AttrType::Link);
+        assert!(!entries
+            .0
+            .contains_key(Path::new("~/path/to/target/dir/file3")));
+    }
+
+    #[test]
+    fn conflicting_merged_sources() {
+        let tempdir = tempfile::tempdir().unwrap();
+        create_tmp_tree(tempdir.path());
+        let load = |file: &str, content: &str| {
+            let mut profile = serde_yaml::from_str::<Profile>(content).unwrap();
+            profile.set_source(file.as_ref(), content, &[]);
+            profile
+        };
+        let mut profile = load("dbot.yaml", "target: path/to/source/file1\n");
+        profile.merge(load("import.yaml", "target: path/to/source/file2\n"));
+        let compile = |later_wins| {
+            compile(
+                &CompilerOptions {
+                    later_wins,
+                    ..compiler_options(tempdir.path(), "~".as_ref())
+                },
+                profile.clone().into_entries().unwrap(),
+            )
+        };
+        let result = compile(false);
+        let Err(error::Error::OverlappingTargets(overlaps)) = result else {
+            panic!("{result:?}");
+        };
+        let origin = |file: &str, source: &str| Origin {
+            profile: "target".into(),
+            source: tempdir.path().join(source),
+            ty: AttrType::Copy,
+            span: Some(Span {
+                file: file.into(),
+                location: crate::profile::Location { line: 1, column: 1 },
+            }),
+        };
+        assert_eq!(
+            overlaps.0,
+            [Overlap {
+                target: "~/target".into(),
+                earlier: origin("dbot.yaml", "path/to/source/file1"),
+                later: origin("import.yaml", "path/to/source/file2"),
+            }]
+        );
+        assert!(overlaps.to_string().contains("in 'import.yaml:1:1'"));
+
+        let entries = compile(true).unwrap();
+        assert_eq!(
+            entries.0[Path::new("~/target")].source,
+            tempdir.path().join("path/to/source/file2")
+        );
+    }
+
+    #[test]
+    fn collect_all_errors() {
+        let tempdir = tempfile::tempdir().unwrap();
+        create_tmp_tree(tempdir.path());
+        let profile = serde_yaml::from_str::<Profile>(
+            r#"
+            target1:
+              +source: path/to/source
+              +type: link
+              child: path/to/source/file1
+            target2: path/to/missing
+            target3:
+              +source: path/to/source
+              +type: template
+            target4: path/to/source
+            "#,
+        )
+        .unwrap();
+        let mut diagnostics = Diagnostics::default();
+        let entries = profile.collect_entries(None, &mut diagnostics);
+        let compiled = compile_all(
+            &compiler_options(tempdir.path(), "~".as_ref()),
+            entries,
+            &mut diagnostics,
+        );
+        assert_eq!(compiled.0.len(), 2);
+        let mut errors = diagnostics
+            .iter()
+            .map(|e| match e {
+                error::Error::UnexpectedChildren(..) => 1,
+                error::Error::MissingSource(..) => 2,
+                error::Error::UnexpectedDirectoryForTemplate(..) => 3,
+                _ => panic!("{e:?}"),
+            })
+            .collect::<Vec<_>>();
+        errors.sort();
+        assert_eq!(errors, [1, 2, 3]);
+    }
+
+    #[test]
+    fn sorted_entries() {
+        let entries = compiled_entries("".as_ref(), &["b", "a/b", "a.b", "a"], AttrType::Copy);
+        let targets = |iter: Vec<(&Path, _)>| {
+            iter.into_iter()
+                .map(|(target, _)| target.strip_prefix("~/path/to/target").unwrap())
+                .map(|target| target.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(targets(entries.iter().collect()), ["a", "a/b", "a.b", "b"]);
+        assert_eq!(
+            targets(entries.iter_rev().collect()),
+            ["b", "a.b", "a/b", "a"]
+        );
+        let content = serde_yaml::to_string(&entries).unwrap();
+        let read = serde_yaml::from_str::<CompiledEntries>(&content).unwrap();
+        assert_eq!(serde_yaml::to_string(&read).unwrap(), content);
+    }
+
+    #[test]
+    fn excluded_sources() {
+        let tempdir = tempfile::tempdir().unwrap();
+        create_tmp_tree(tempdir.path());
+        let profile = serde_yaml::from_str::<Profile>(
+            r#"
+            path/to/target: path/to/source
+            path/to/excluded: path/to/source/file1
+            "#,
+        )
+        .unwrap();
+        let entries = compile(
+            &CompilerOptions {
+                excluded: vec![tempdir.path().join("path/to/source/file1")],
+                ..compiler_options(tempdir.path(), "~".as_ref())
+            },
+            profile.into_entries().unwrap(),
+        )
+        .unwrap();
+        let expected = compiled_entries(tempdir.path(), &["file2"], AttrType::Copy);
+        assert_eq!(entries, expected);
+    }
 }
@@ -1,3 +1,4 @@
+use crate::{compile::Overlaps, profile::Location};
 use std::path::PathBuf;
 use thisctx::WithContext;
 use thiserror::Error;
@@ -5,21 +6,98 @@ use thiserror::Error;
 pub type Result<T> = std::result::Result<T, Error>;
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
+/// Where an error occurs in a profile file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Span {
+    pub file: PathBuf,
+    pub location: Location,
+}
+
 #[derive(Debug, Error, WithContext)]
 #[thisctx(pub(crate), attr(derive(Debug)))]
 pub enum Error {
     #[error("A linked or template file cannot have children at '{0}'")]
-    UnexpectedChildren(PathBuf),
+    UnexpectedChildren(PathBuf, Option<Span>),
     #[error("Invalid pattern set found at '{0}'")]
-    InvalidPatternSet(PathBuf),
+    InvalidPatternSet(PathBuf, Option<Span>),
+    #[error("Invalid pattern '{1}'")]
+    InvalidPattern(#[source] globset::Error, String),
+    #[error("Invalid path '{0}'")]
+    InvalidPath(PathBuf),
     #[error("Invalid profile at '{0}'")]
     InvalidProfile(PathBuf),
+    #[error("Undefined attribute '{0}' at '{1}'")]
+    UnknownAttribute(String, PathBuf, Option<Span>),
+    #[error("Empty ignore set at '{0}'")]
+    EmptyIgnore(PathBuf, Option<Span>),
+    #[error("Unknown key '{0}'")]
+    UnknownKey(String, Option<Span>),
+    #[error("Overlapping targets found in profile:\n{0}")]
+    OverlappingTargets(Overlaps),
+    #[error("Target already exists at '{0}'")]
+    Conflict(PathBuf),
+    #[error("Cannot move '{0}' over existing '{1}'")]
+    DestinationExists(PathBuf, PathBuf),
     #[error("IO failed at '{1}'")]
     IoFailed(#[source] std::io::Error, PathBuf),
+    #[error("Source not found: '{0}'")]
+    MissingSource(PathBuf, Option<Span>),
     #[error("A template cannot be created from a directory: '{0}'")]
-    UnexpectedDirectoryForTemplate(PathBuf),
-    #[error("Render failed at '{1}'")]
-    RenderError(#[source] BoxError, PathBuf),
+    UnexpectedDirectoryForTemplate(PathBuf, Option<Span>),
+    #[error("Failed to render '{1}' into '{2}'")]
+    RenderError(#[source] BoxError, PathBuf, PathBuf),
     #[error("Symlinks are only supported on unix systems: '{0}'")]
     UnsupportedSymlinks(PathBuf),
 }
+
+impl Error {
+    /// Returns where the error occurs in a profile file, if known.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Error::UnexpectedChildren(_, span)
+            | Error::InvalidPatternSet(_, span)
+            | Error::UnknownAttribute(_, _, span)
+            | Error::EmptyIgnore(_, span)
+            | Error::UnknownKey(_, span)
+            | Error::MissingSource(_, span)
+            | Error::UnexpectedDirectoryForTemplate(_, span) => span.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Errors collected while going through a whole profile instead of stopping
+/// at the first one.
+#[derive(Debug, Default)]
+pub struct Diagnostics(Vec<Error>);
+
+impl Diagnostics {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Error> {
+        self.0.iter()
+    }
+
+    pub fn push(&mut self, err: Error) {
+        self.0.push(err);
+    }
+
+    /// Records the error of `result` if any.
+    pub(crate) fn record<T>(&mut self, result: Result<T>) -> Option<T> {
+        result.map_err(|e| self.push(e)).ok()
+    }
+
+    /// Returns `value` if nothing has been collected, or the first error.
+    pub fn into_result<T>(self, value: T) -> Result<T> {
+        match self.0.into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(value),
+        }
+    }
+}
@@ -3,7 +3,10 @@ mod utils;
 
 /// Apply profiles to target path.
 mod apply;
-pub use apply::{apply, TemplateRenderer};
+pub use apply::{
+    apply, apply_in, expected_contents, Applied, ApplyOptions, ConflictPolicy, RenderContext,
+    TemplateRenderer,
+};
 
 /// Compile profiles.
 pub mod compile;
@@ -20,6 +23,18 @@ pub use merge::Merge;
 /// Patterns used to match paths.
 mod pattern;
 
+/// Plan changes before applying profiles.
+pub mod plan;
+pub use plan::{plan, Plan};
+
 /// Use defined profiles.
 pub mod profile;
 pub use profile::Profile;
+
+/// Detect changes made to targets since they were applied.
+pub mod status;
+pub use status::{status, Fingerprint};
+
+/// Record and undo changes made to the filesystem.
+mod transaction;
+pub use transaction::Transaction;
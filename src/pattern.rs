@@ -1,6 +1,7 @@
 mod de;
 
 use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{ser::SerializeSeq, Serialize};
 use std::path::Path;
 
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
@@ -26,7 +27,29 @@ impl PatternSetBuilder {
         for pat in self.globs.iter().cloned() {
             builder.add(pat);
         }
-        builder.build().map(PatternSet)
+        builder.build().map(|set| PatternSet {
+            set,
+            globs: self.globs.clone(),
+        })
+    }
+}
+
+/// Writes a single pattern as a string, which is the common case.
+impl Serialize for PatternSetBuilder {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self.globs.as_slice() {
+            [glob] => serializer.serialize_str(glob.glob()),
+            globs => {
+                let mut seq = serializer.serialize_seq(Some(globs.len()))?;
+                for glob in globs {
+                    seq.serialize_element(glob.glob())?;
+                }
+                seq.end()
+            }
+        }
     }
 }
 
@@ -37,7 +60,10 @@ impl Extend<Glob> for PatternSetBuilder {
 }
 
 #[derive(Clone, Debug, Default)]
-pub struct PatternSet(GlobSet);
+pub struct PatternSet {
+    set: GlobSet,
+    globs: Vec<Glob>,
+}
 
 #[cfg(test)]
 impl PartialEq for PatternSet {
@@ -51,6 +77,11 @@ impl Eq for PatternSet {}
 
 impl PatternSet {
     pub fn is_match<P: AsRef<Path>>(&self, path: P) -> bool {
-        self.0.is_match(path.as_ref())
+        self.set.is_match(path.as_ref())
+    }
+
+    /// Returns the patterns this set is built from.
+    pub fn patterns(&self) -> impl Iterator<Item = &str> {
+        self.globs.iter().map(Glob::glob)
     }
 }
@@ -0,0 +1,268 @@
+use crate::{
+    apply::{expected_contents, TemplateRenderer},
+    compile::{CompiledEntries, CompiledProfile},
+    error,
+    profile::AttrType,
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+use thisctx::WithContext;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// The target doesn't exist and will be created.
+    Create,
+    /// The target exists with different contents and will be overwritten.
+    Overwrite,
+    /// The target is a symlink which doesn't point to the expected source.
+    ReplaceSymlink,
+    /// The target is already up to date.
+    Unchanged,
+    /// The target was created last time but is no longer managed.
+    Remove,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Action::Create => "create",
+            Action::Overwrite => "overwrite",
+            Action::ReplaceSymlink => "replace-symlink",
+            Action::Unchanged => "unchanged",
+            Action::Remove => "remove",
+        })
+    }
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Plan(pub(crate) Vec<(PathBuf, Action)>);
+
+impl Plan {
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, Action)> {
+        self.0
+            .iter()
+            .map(|(path, action)| (path.as_path(), *action))
+    }
+
+    /// Returns `true` if applying this plan doesn't touch any target.
+    pub fn is_unchanged(&self) -> bool {
+        self.0
+            .iter()
+            .all(|(_, action)| *action == Action::Unchanged)
+    }
+}
+
+/// Computes what [`apply`](crate::apply) would do to the targets of `entries`
+/// without writing anything. Targets only found in `previous` are planned to be
+/// removed.
+pub fn plan<R: TemplateRenderer>(
+    renderer: &mut R,
+    entries: &CompiledEntries,
+    previous: Option<&CompiledEntries>,
+) -> error::Result<Plan>
+where
+    R::Err: 'static + std::error::Error + Send + Sync,
+{
+    // Removed targets are interleaved with managed ones in path order.
+    let mut actions = BTreeMap::new();
+    for (target, profile) in entries.iter() {
+        let action = plan_entry(renderer, target, profile)?;
+        actions.insert(target.to_owned(), action);
+    }
+    if let Some(previous) = previous {
+        for (target, _) in previous.iter() {
+            if !entries.0.contains_key(target) && target.symlink_metadata().is_ok() {
+                actions.insert(target.to_owned(), Action::Remove);
+            }
+        }
+    }
+    Ok(Plan(actions.into_iter().collect()))
+}
+
+pub(crate) fn plan_entry<R: TemplateRenderer>(
+    renderer: &mut R,
+    target: &Path,
+    profile: &CompiledProfile,
+) -> error::Result<Action>
+where
+    R::Err: 'static + std::error::Error + Send + Sync,
+{
+    let metadata = match target.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Action::Create),
+        Err(e) => return Err(e).context(error::IoFailed(target)),
+    };
+    if metadata.is_symlink() {
+        let original = std::fs::read_link(target).context(error::IoFailed(target))?;
+        return Ok(
+            if profile.ty == AttrType::Link && original == profile.link_original(target) {
+                Action::Unchanged
+            } else {
+                Action::ReplaceSymlink
+            },
+        );
+    }
+    let unchanged = metadata.is_file()
+        && profile.ty != AttrType::Link
+        && expected_contents(renderer, target, profile)?
+            == std::fs::read(target).context(error::IoFailed(target))?;
+    Ok(if unchanged {
+        Action::Unchanged
+    } else {
+        Action::Overwrite
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        apply,
+        utils::{apply_options, compile_profile, EmptyRenderer},
+        ConflictPolicy,
+    };
+
+    fn apply_default(entries: &CompiledEntries) {
+        let options = apply_options(ConflictPolicy::Error, "".as_ref());
+        apply(&mut EmptyRenderer, entries, &options).unwrap();
+    }
+
+    fn create_tmp_tree(tmp: &Path) {
+        create_tree!(tmp, {
+            path: {
+                to: {
+                    source: {
+                        file1,
+                        file2,
+                    },
+                },
+            },
+        });
+        std::fs::write(tmp.join("path/to/source/file1"), "file1").unwrap();
+    }
+
+    fn expected_plan(target: &Path, actions: &[(&str, Action)]) -> Plan {
+        Plan(
+            actions
+                .iter()
+                .map(|(path, action)| (target.join(path), *action))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn plan_create() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        create_tmp_tree(source.path());
+        let entries = compile_profile(
+            r#"
+            path/to/target: path/to/source
+            "#,
+            source.path(),
+            target.path(),
+        );
+        let plan = plan(&mut EmptyRenderer, &entries, None).unwrap();
+        let expected = expected_plan(
+            target.path(),
+            &[
+                ("path/to/target/file1", Action::Create),
+                ("path/to/target/file2", Action::Create),
+            ],
+        );
+        assert_eq!(plan, expected);
+        assert!(!target.path().join("path").exists());
+    }
+
+    #[test]
+    fn plan_unchanged_and_overwrite() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        create_tmp_tree(source.path());
+        let entries = compile_profile(
+            r#"
+            path/to/target:
+              +source: path/to/source
+              +type: template
+              +recursive: true
+            "#,
+            source.path(),
+            target.path(),
+        );
+        apply_default(&entries);
+        assert!(plan(&mut EmptyRenderer, &entries, None)
+            .unwrap()
+            .is_unchanged());
+        std::fs::write(target.path().join("path/to/target/file2"), "modified").unwrap();
+        let plan = plan(&mut EmptyRenderer, &entries, None).unwrap();
+        let expected = expected_plan(
+            target.path(),
+            &[
+                ("path/to/target/file1", Action::Unchanged),
+                ("path/to/target/file2", Action::Overwrite),
+            ],
+        );
+        assert_eq!(plan, expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plan_replace_symlink() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        create_tmp_tree(source.path());
+        let entries = compile_profile(
+            r#"
+            path/to/target:
+              +source: path/to/source
+              +type: link
+            "#,
+            source.path(),
+            target.path(),
+        );
+        apply_default(&entries);
+        assert!(plan(&mut EmptyRenderer, &entries, None)
+            .unwrap()
+            .is_unchanged());
+        let link = target.path().join("path/to/target");
+        std::fs::remove_file(&link).unwrap();
+        std::os::unix::fs::symlink(source.path().join("path"), &link).unwrap();
+        let plan = plan(&mut EmptyRenderer, &entries, None).unwrap();
+        let expected = expected_plan(target.path(), &[("path/to/target", Action::ReplaceSymlink)]);
+        assert_eq!(plan, expected);
+    }
+
+    #[test]
+    fn plan_remove() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        create_tmp_tree(source.path());
+        let previous = compile_profile(
+            r#"
+            path/to/target: path/to/source
+            "#,
+            source.path(),
+            target.path(),
+        );
+        apply_default(&previous);
+        let entries = compile_profile(
+            r#"
+            path/to/target/file1: path/to/source/file1
+            "#,
+            source.path(),
+            target.path(),
+        );
+        let plan = plan(&mut EmptyRenderer, &entries, Some(&previous)).unwrap();
+        let expected = expected_plan(
+            target.path(),
+            &[
+                ("path/to/target/file1", Action::Unchanged),
+                ("path/to/target/file2", Action::Remove),
+            ],
+        );
+        assert_eq!(plan, expected);
+    }
+}
@@ -1,25 +1,41 @@
 mod de;
+mod origin;
 mod parse;
+mod schema;
+mod ser;
+mod when;
 
 use crate::{
-    error,
+    error::{self, Diagnostics},
     merge::Merge,
     pattern::{PatternSet, PatternSetBuilder},
 };
+pub use de::ATTRIBUTES;
+use globset::Glob;
 use once_cell::sync::OnceCell;
+pub use origin::{Location, Origin, OriginKind, Origins};
+pub use schema::schema;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     ffi::OsStr,
+    fmt,
     path::{Component, Path, PathBuf},
     rc::Rc,
 };
-use thisctx::{IntoError, WithContext};
+use thisctx::WithContext;
+pub use when::{Conditions, Host};
 
 fn rc_unwrap_or_clone<T: Clone>(rc: Rc<T>) -> T {
     Rc::try_unwrap(rc).unwrap_or_else(|rc| (*rc).clone())
 }
 
+fn extend_conditions(this: Rc<Conditions>, parent: &Conditions) -> Rc<Conditions> {
+    let mut this = rc_unwrap_or_clone(this);
+    this.0.extend(parent.0.iter().cloned());
+    Rc::new(this)
+}
+
 fn extend_set_build(
     this: Rc<CachedPatternSetBuilder>,
     parent: &CachedPatternSetBuilder,
@@ -29,7 +45,7 @@ fn extend_set_build(
     Rc::new(this)
 }
 
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(transparent)]
 pub struct Profile {
     root: ProfileNode,
@@ -39,14 +55,138 @@ pub struct Profile {
 #[cfg_attr(test, derive(Eq, PartialEq))]
 pub struct ProfileEntries(pub(crate) Vec<(PathBuf, ProfileAttr)>);
 
+impl ProfileEntries {
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &ProfileAttr)> {
+        self.0.iter().map(|(path, attr)| (path.as_path(), attr))
+    }
+
+    /// Finds the most specific entry whose target contains `target`, which
+    /// is relative to the target directory.
+    pub fn find(&self, target: &Path) -> Option<(&Path, &ProfileAttr)> {
+        self.iter()
+            .filter(|(path, _)| target.starts_with(path))
+            .max_by_key(|(path, _)| path.components().count())
+    }
+}
+
+/// Finds where the key nested in `keys` is defined in the YAML document.
+pub fn locate_key(content: &str, keys: &[&str]) -> Option<Location> {
+    de::KeyLocations::parse(content)?.locate(keys)
+}
+
+/// Normalizes `path` in the same way as target and source paths in profiles.
+pub fn normalize_path(path: &Path) -> error::Result<PathBuf> {
+    path.to_str()
+        .and_then(|s| parse::normalize_path(s).ok())
+        .context(error::InvalidPath(path))
+}
+
 impl Merge for Profile {
-    fn merge(&mut self, other: Profile) {
+    fn merge(&mut self, mut other: Profile) {
+        other
+            .root
+            .for_each_origin(&mut |origin| origin.kind = OriginKind::Merged);
         self.root.merge(other.root);
     }
 }
 
 impl Profile {
-    pub fn into_entries(mut self) -> error::Result<ProfileEntries> {
+    /// Records `file` as where attributes of this profile are defined.
+    pub fn set_file(&mut self, file: &Path) {
+        let file = Rc::<Path>::from(file);
+        self.root
+            .for_each_origin(&mut |origin| origin.file = Some(file.clone()));
+    }
+
+    /// Records `file` as where this profile is defined and locates attributes
+    /// in `content` of the file, where the profile is found under `keys` of
+    /// the document.
+    pub fn set_source(&mut self, file: &Path, content: &str, keys: &[&str]) {
+        self.set_file(file);
+        let Some(locations) = de::KeyLocations::parse(content) else {
+            return;
+        };
+        let mut locations = &locations;
+        for key in keys {
+            match locations.get(key) {
+                Some(children) => locations = children,
+                None => return,
+            }
+        }
+        self.root.locate(locations, None);
+    }
+
+    /// Returns the node defined exactly at `target`.
+    pub fn get(&self, target: &Path) -> Option<&ProfileNode> {
+        let target = normalize_path(target).ok()?;
+        self.root.find(&target)
+    }
+
+    /// Inserts `node` at `target` and returns the nodes defined there before,
+    /// see [`Profile::remove`].
+    pub fn insert(
+        &mut self,
+        target: &Path,
+        node: ProfileNode,
+    ) -> error::Result<Option<ProfileNode>> {
+        let target = normalize_path(target)?;
+        if target.as_os_str().is_empty() {
+            return Ok(Some(std::mem::replace(&mut self.root, node)));
+        }
+        let removed = self.root.remove(&target);
+        self.root.children.insert(target, node);
+        Ok(removed)
+    }
+
+    /// Removes all nodes defined exactly at `target`, together with their
+    /// children, and returns them merged in the order of their paths.
+    pub fn remove(&mut self, target: &Path) -> Option<ProfileNode> {
+        let target = normalize_path(target).ok()?;
+        self.root.remove(&target)
+    }
+
+    /// Mounts the whole profile under `target`, where sources defined in it
+    /// are prefixed with `source`, which makes a profile reusable as a module.
+    /// Nodes inheriting no `source` take their own target under the prefix if
+    /// they are leaves or define a type, while the root never becomes an entry.
+    pub fn mount(&mut self, target: &Path, source: &Path) -> error::Result<()> {
+        let target = normalize_path(target)?;
+        let source = normalize_path(source)?;
+        if !source.as_os_str().is_empty() {
+            self.root.prefix_sources(&source, Some("".as_ref()));
+        }
+        if !target.as_os_str().is_empty() {
+            let root = std::mem::take(&mut self.root);
+            self.root.children.insert(target, root);
+        }
+        Ok(())
+    }
+
+    /// Checks problems which are tolerated unless in strict mode, i.e.
+    /// undefined attributes and empty ignore sets.
+    pub fn validate(&self, diagnostics: &mut Diagnostics) {
+        self.root.validate("".as_ref(), diagnostics);
+    }
+
+    /// Collects entries with conditions evaluated against the current machine.
+    pub fn into_entries(self) -> error::Result<ProfileEntries> {
+        self.into_entries_with(&Host::current())
+    }
+
+    pub fn into_entries_with(self, host: &Host) -> error::Result<ProfileEntries> {
+        let mut diagnostics = Diagnostics::default();
+        let entries = self.collect_entries(Some(host), &mut diagnostics);
+        diagnostics.into_result(entries)
+    }
+
+    /// Collects entries from the whole profile, where invalid entries are
+    /// skipped and their errors are recorded in `diagnostics`. Conditions are
+    /// ignored if `host` is `None`.
+    pub fn collect_entries(
+        mut self,
+        host: Option<&Host>,
+        diagnostics: &mut Diagnostics,
+    ) -> ProfileEntries {
         let mut collect_to = ProfileEntries(<_>::default());
         let node = self.build_component_tree();
         collect_entries_from_node(
@@ -54,10 +194,12 @@ impl Profile {
             node,
             "".as_ref(),
             &<_>::default(),
+            host,
             &mut collect_to,
-        )?;
+            diagnostics,
+        );
         collect_to.0.sort_by(|(a, _), (b, _)| a.cmp(b));
-        Ok(collect_to)
+        collect_to
     }
 
     /// Splits target paths into components and constructs a attributes tree.
@@ -68,7 +210,7 @@ impl Profile {
     /// during [`collect_entries`].
     fn build_component_tree(&mut self) -> ComponentNode<'_> {
         let mut tree = ComponentNode::default();
-        update_component_tree("".as_ref(), &mut self.root, &mut tree);
+        update_component_tree("".as_ref(), "".as_ref(), &mut self.root, &mut tree);
         tree
     }
 }
@@ -78,20 +220,33 @@ fn collect_entries_from_node(
     node: ComponentNode<'_>,
     parent_target: &Path,
     parent: &ProfileAttrBuilder,
+    host: Option<&Host>,
     collect_to: &mut ProfileEntries,
-) -> error::Result<()> {
-    let ComponentNode { mut attr, children } = node;
+    diagnostics: &mut Diagnostics,
+) {
+    let ComponentNode { attr, children } = node;
     let full_target = parent_target.join(target);
 
     // 1) Inherit attribute.
-    attr = inherit_attr(target, attr, parent)?;
+    let Some(attr) = diagnostics.record(inherit_attr(target, attr, parent)) else {
+        return;
+    };
+
+    // Skip the whole subtree if conditions are not satisfied.
+    if let (Some(when), Some(host)) = (attr.when.as_ref(), host) {
+        if !when.matches(host) {
+            return;
+        }
+    }
 
     // 2) Validate attribute.
     if matches!(attr.ty, Some(AttrType::Template | AttrType::Link))
         && !matches!(attr.recursive, Some(true))
         && !children.is_empty()
     {
-        return error::UnexpectedChildren(full_target).fail();
+        let span = attr.origins.span("type");
+        diagnostics.push(error::Error::UnexpectedChildren(full_target, span));
+        return;
     }
 
     // 3) Collect from child nodes.
@@ -101,16 +256,17 @@ fn collect_entries_from_node(
             child_node,
             &full_target,
             &attr,
+            host,
             collect_to,
-        )?;
+            diagnostics,
+        );
     }
 
     // 4) Build attribute and collect to entries.
     // Ignore entries without `source` attribute.
-    if let Some(attr) = attr.build(&full_target)? {
+    if let Some(Some(attr)) = diagnostics.record(attr.build(&full_target)) {
         collect_to.0.push((full_target, attr));
     }
-    Ok(())
 }
 
 fn inherit_attr(
@@ -128,25 +284,47 @@ fn inherit_attr(
     // Attributes to override.
     let ty = attr.ty.or(parent.ty);
     let recursive = attr.recursive.or(parent.recursive);
+    let mode = attr.mode.or(parent.mode);
+    let executable = attr.executable.or(parent.executable);
+    let private = attr.private.or(parent.private);
+    let preserve_mode = attr.preserve_mode.or(parent.preserve_mode);
+    let relative = attr.relative.or(parent.relative);
     // Attributes to extend.
     let ignore = match (attr.ignore, parent.ignore.clone()) {
         (Some(this), Some(parent)) => Some(extend_set_build(this, &parent)),
         (Some(val), _) | (_, Some(val)) => Some(val),
         _ => None,
     };
+    let when = match (attr.when, parent.when.clone()) {
+        (Some(this), Some(parent)) => Some(extend_conditions(this, &parent)),
+        (Some(val), _) | (_, Some(val)) => Some(val),
+        _ => None,
+    };
+    let mut origins = attr.origins;
+    origins.inherit(&parent.origins);
     Ok(ProfileAttrBuilder {
         source,
         ty,
         recursive,
         ignore,
+        when,
+        mode,
+        executable,
+        private,
+        preserve_mode,
+        relative,
+        origins,
+        overridden: attr.overridden,
     })
 }
 
 fn update_component_tree<'a>(
+    parent_target: &Path,
     target: &'a Path,
     node: &'a mut ProfileNode,
     mut tree: &mut ComponentNode<'a>,
 ) {
+    let full_target = parent_target.join(target);
     // Update `tree` to the last component of `target`.
     for compo in target.components() {
         match compo {
@@ -154,20 +332,48 @@ fn update_component_tree<'a>(
             _ => unreachable!(),
         }
     }
+    node.attr
+        .origins
+        .for_each_mut(|origin| origin.node = full_target.clone());
     tree.attr.merge(std::mem::take(&mut node.attr));
-    for (child_target, child_node) in node.children.iter_mut() {
-        update_component_tree(child_target, child_node, tree);
+    // Merge children in the order of their paths to make overridden
+    // attributes deterministic.
+    let mut children = node.children.iter_mut().collect::<Vec<_>>();
+    children.sort_by_key(|(target, _)| *target);
+    for (child_target, child_node) in children {
+        update_component_tree(&full_target, child_target, child_node, tree);
     }
 }
 
 impl Merge for ProfileAttrBuilder {
     fn merge(&mut self, other: ProfileAttrBuilder) {
+        // Records a different source being replaced, which is a conflict
+        // reported when compiling.
+        if let (Some(this), Some(that)) = (&self.source, &other.source) {
+            if this != that {
+                self.overridden.push(OverriddenSource {
+                    source: this.clone(),
+                    ty: self.ty,
+                    origins: self.origins.get("source").to_vec(),
+                });
+            }
+        }
+        self.overridden.extend(other.overridden);
         macro_rules! merge_field {
             ($($field:ident),*) => {$(
                 self.$field.merge(other.$field);
             )*};
         }
-        merge_field!(source, ty, recursive);
+        merge_field!(
+            source,
+            ty,
+            recursive,
+            mode,
+            executable,
+            private,
+            preserve_mode,
+            relative
+        );
         if let Some(other) = other.ignore {
             if !other.builder.is_empty() {
                 let mut this = self
@@ -182,6 +388,13 @@ impl Merge for ProfileAttrBuilder {
                 self.ignore = Some(Rc::new(this));
             }
         }
+        self.origins.merge(other.origins);
+        if let Some(other) = other.when {
+            self.when = Some(match self.when.take() {
+                Some(this) => extend_conditions(this, &other),
+                None => other,
+            });
+        }
     }
 }
 
@@ -191,9 +404,17 @@ struct ProfileAttrBuilder {
     ty: Option<AttrType>,
     recursive: Option<bool>,
     ignore: Option<Rc<CachedPatternSetBuilder>>,
+    when: Option<Rc<Conditions>>,
+    mode: Option<u32>,
+    executable: Option<bool>,
+    private: Option<bool>,
+    preserve_mode: Option<bool>,
+    relative: Option<bool>,
+    origins: Origins,
+    overridden: Vec<OverriddenSource>,
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(transparent)]
 struct CachedPatternSetBuilder {
     builder: PatternSetBuilder,
@@ -225,6 +446,14 @@ impl ProfileAttrBuilder {
             ty,
             recursive,
             ignore,
+            mode,
+            executable,
+            private,
+            preserve_mode,
+            relative,
+            origins,
+            overridden,
+            ..
         } = self;
         if let Some(source) = source {
             Ok(Some(ProfileAttr {
@@ -232,9 +461,23 @@ impl ProfileAttrBuilder {
                 ty: ty.unwrap_or_default(),
                 recursive: recursive.unwrap_or_default(),
                 ignore: match ignore {
-                    Some(builder) => builder.build().context(error::InvalidPatternSet(target))?,
+                    Some(builder) => {
+                        let span = origins.span("ignore");
+                        builder
+                            .build()
+                            .context(error::InvalidPatternSet(target, span))?
+                    }
                     None => <_>::default(),
                 },
+                permissions: Permissions {
+                    mode,
+                    executable: executable.unwrap_or_default(),
+                    private: private.unwrap_or_default(),
+                    preserve_mode,
+                },
+                relative,
+                origins,
+                overridden,
             }))
         } else {
             Ok(None)
@@ -249,6 +492,61 @@ pub struct ProfileAttr {
     pub ty: AttrType,
     pub recursive: bool,
     pub ignore: Rc<PatternSet>,
+    pub permissions: Permissions,
+    /// Whether to create relative symlinks, uses the default of the compiler
+    /// if unspecified.
+    pub relative: Option<bool>,
+    /// Where each attribute is defined.
+    pub origins: Origins,
+    /// Sources replaced by `source` when merging nodes of the same target.
+    pub overridden: Vec<OverriddenSource>,
+}
+
+/// A source of a node replaced by a different one defined for the same
+/// target, e.g. by an imported profile.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OverriddenSource {
+    pub source: PathBuf,
+    pub ty: Option<AttrType>,
+    /// Where the replaced source is defined.
+    pub origins: Vec<Origin>,
+}
+
+/// Permissions to set on target files after they are written.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Permissions {
+    /// Exact mode of target files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Adds execute bits wherever read bits are set.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub executable: bool,
+    /// Removes all permissions of group and others.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+    /// Uses the mode of source files instead of the default one, which is
+    /// enabled for copied files unless specified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preserve_mode: Option<bool>,
+}
+
+impl Permissions {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Computes the mode of a target from the `base` one, which is either the
+    /// mode of its source or of the file just written.
+    pub fn resolve(&self, base: u32) -> u32 {
+        let mut mode = self.mode.unwrap_or(base);
+        if self.executable {
+            mode |= (mode & 0o444) >> 2;
+        }
+        if self.private {
+            mode &= !0o077;
+        }
+        mode
+    }
 }
 
 #[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
@@ -260,16 +558,206 @@ pub enum AttrType {
     Template,
 }
 
+impl fmt::Display for AttrType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            AttrType::Copy => "copy",
+            AttrType::Link => "link",
+            AttrType::Template => "template",
+        })
+    }
+}
+
+/// A node of the profile, which defines attributes of a target and nested
+/// nodes of its children.
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
-struct ProfileNode {
+pub struct ProfileNode {
     attr: ProfileAttrBuilder,
     children: HashMap<PathBuf, ProfileNode>,
+    /// Undefined attributes and where they are found.
+    unknown: Vec<(String, Origin)>,
 }
 
 impl Merge for ProfileNode {
     fn merge(&mut self, other: Self) {
         self.attr.merge(other.attr);
         self.children.merge(other.children);
+        self.unknown.extend(other.unknown);
+    }
+}
+
+impl ProfileNode {
+    /// Creates a node which copies `source` by default.
+    pub fn from_source(source: &Path) -> error::Result<Self> {
+        let source = normalize_path(source)?;
+        Ok(Self::default().with_attr(|attr| attr.source = Some(source)))
+    }
+
+    pub fn source(&self) -> Option<&Path> {
+        self.attr.source.as_deref()
+    }
+
+    pub fn ty(&self) -> Option<AttrType> {
+        self.attr.ty
+    }
+
+    pub fn with_type(self, ty: AttrType) -> Self {
+        self.with_attr(|attr| attr.ty = Some(ty))
+    }
+
+    pub fn with_recursive(self, recursive: bool) -> Self {
+        self.with_attr(|attr| attr.recursive = Some(recursive))
+    }
+
+    /// Adds glob `patterns` of sources to ignore.
+    pub fn with_ignore<I, S>(self, patterns: I) -> error::Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut ignore = CachedPatternSetBuilder::default();
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            let glob = Glob::new(pattern).context(error::InvalidPattern(pattern))?;
+            ignore.builder.extend([glob]);
+        }
+        Ok(self.with_attr(|attr| attr.ignore = Some(Rc::new(ignore))))
+    }
+
+    pub fn with_mode(self, mode: u32) -> Self {
+        self.with_attr(|attr| attr.mode = Some(mode))
+    }
+
+    pub fn with_executable(self, executable: bool) -> Self {
+        self.with_attr(|attr| attr.executable = Some(executable))
+    }
+
+    pub fn with_private(self, private: bool) -> Self {
+        self.with_attr(|attr| attr.private = Some(private))
+    }
+
+    pub fn with_preserve_mode(self, preserve_mode: bool) -> Self {
+        self.with_attr(|attr| attr.preserve_mode = Some(preserve_mode))
+    }
+
+    pub fn with_relative(self, relative: bool) -> Self {
+        self.with_attr(|attr| attr.relative = Some(relative))
+    }
+
+    /// Adds `child` at `target` relative to this node, which replaces the
+    /// child defined with the same path.
+    pub fn with_child(mut self, target: &Path, child: ProfileNode) -> error::Result<Self> {
+        let target = normalize_path(target)?;
+        self.children.insert(target, child);
+        Ok(self)
+    }
+
+    fn with_attr(mut self, f: impl FnOnce(&mut ProfileAttrBuilder)) -> Self {
+        f(&mut self.attr);
+        self.attr.origins = Origins::own(&self.attr);
+        self
+    }
+
+    /// Returns children sorted by their paths.
+    fn sorted_children(&self) -> Vec<(&Path, &ProfileNode)> {
+        let mut children = self
+            .children
+            .iter()
+            .map(|(target, child)| (target.as_path(), child))
+            .collect::<Vec<_>>();
+        children.sort_by_key(|(target, _)| *target);
+        children
+    }
+
+    fn find(&self, target: &Path) -> Option<&ProfileNode> {
+        if target.as_os_str().is_empty() {
+            return Some(self);
+        }
+        self.sorted_children()
+            .into_iter()
+            .find_map(|(path, child)| child.find(target.strip_prefix(path).ok()?))
+    }
+
+    fn remove(&mut self, target: &Path) -> Option<ProfileNode> {
+        let mut paths = self.children.keys().cloned().collect::<Vec<_>>();
+        paths.sort();
+        let mut removed = None::<ProfileNode>;
+        for path in paths {
+            let Ok(rest) = target.strip_prefix(&path) else {
+                continue;
+            };
+            let node = if rest.as_os_str().is_empty() {
+                self.children.remove(&path)
+            } else {
+                self.children
+                    .get_mut(&path)
+                    .and_then(|child| child.remove(rest))
+            };
+            match (&mut removed, node) {
+                (Some(removed), Some(node)) => removed.merge(node),
+                (removed, node) => removed.merge(node),
+            }
+        }
+        removed
+    }
+
+    /// Prefixes sources with `prefix`, where `target` is the path of the node
+    /// relative to the mount point unless one of its parents has a `source`.
+    fn prefix_sources(&mut self, prefix: &Path, target: Option<&Path>) {
+        match (self.attr.source.as_mut(), target) {
+            (Some(source), _) => *source = prefix.join(&*source),
+            (None, Some(target))
+                if !target.as_os_str().is_empty()
+                    && (self.children.is_empty() || self.attr.ty.is_some()) =>
+            {
+                self.attr.source = Some(prefix.join(target));
+            }
+            _ => {}
+        }
+        for overridden in self.attr.overridden.iter_mut() {
+            overridden.source = prefix.join(&overridden.source);
+        }
+        let inherited = self.attr.source.is_some();
+        for (child_target, child) in self.children.iter_mut() {
+            let target = target
+                .filter(|_| !inherited)
+                .map(|target| target.join(child_target));
+            child.prefix_sources(prefix, target.as_deref());
+        }
+    }
+
+    fn for_each_origin(&mut self, f: &mut impl FnMut(&mut Origin)) {
+        self.attr.origins.for_each_mut(&mut *f);
+        self.attr
+            .overridden
+            .iter_mut()
+            .flat_map(|overridden| overridden.origins.iter_mut())
+            .for_each(&mut *f);
+        self.unknown.iter_mut().for_each(|(_, origin)| f(origin));
+        for child in self.children.values_mut() {
+            child.for_each_origin(f);
+        }
+    }
+
+    fn validate(&self, target: &Path, diagnostics: &mut Diagnostics) {
+        for (name, origin) in self.unknown.iter() {
+            diagnostics.push(error::Error::UnknownAttribute(
+                name.to_owned(),
+                target.to_owned(),
+                origin.span(),
+            ));
+        }
+        if let Some(ignore) = self.attr.ignore.as_ref() {
+            if ignore.builder.is_empty() {
+                diagnostics.push(error::Error::EmptyIgnore(
+                    target.to_owned(),
+                    self.attr.origins.span("ignore"),
+                ));
+            }
+        }
+        for (child_target, child) in self.children.iter() {
+            child.validate(&target.join(child_target), diagnostics);
+        }
     }
 }
 
@@ -354,20 +842,20 @@ mod tests {
             path:
               to:
                 target1:
-                  ~source: path/to/source1
-                  ~type: link
+                  +source: path/to/source1
+                  +type: link
               to/target1:
-                  ~type: template
+                  +type: template
             path/to/target1:
-              ~type: link
-              ~recursive: true
+              +type: link
+              +recursive: true
             "#,
         );
         let attr = ProfileAttrBuilder {
             source: Some("path/to/source1".into()),
             ty: Some(AttrType::Link),
             recursive: Some(true),
-            ignore: None,
+            ..Default::default()
         };
         let expected = create_component_node([(
             "path",
@@ -424,10 +912,10 @@ mod tests {
         let entries = profile_from_str(
             r#"
             path/to/target:
-              ~source: path/to/source
+              +source: path/to/source
               child1: path/to/child1
               child2:
-                ~type: link
+                +type: link
             "#,
         )
         .into_entries()
@@ -456,6 +944,10 @@ mod tests {
                 ty: AttrType::Link,
                 recursive: false,
                 ignore: <_>::default(),
+                permissions: <_>::default(),
+                relative: None,
+                origins: <_>::default(),
+                overridden: Vec::new(),
             },
         ));
         assert_eq!(entries, expected);
@@ -467,7 +959,7 @@ mod tests {
     }
 
     fn expects_unexpected_children(path: &Path) -> impl '_ + FnOnce(error::Error) -> bool {
-        move |err| matches!(err, error::Error::UnexpectedChildren(p) if p == path)
+        move |err| matches!(err, error::Error::UnexpectedChildren(p, _) if p == path)
     }
 
     #[test]
@@ -475,8 +967,8 @@ mod tests {
         test_into_entries_error(
             r#"
             path/to/target:
-              ~source: path/to/source
-              ~type: link
+              +source: path/to/source
+              +type: link
               child1: path/to/child1
             "#,
             expects_unexpected_children("path/to/target".as_ref()),
@@ -484,12 +976,249 @@ mod tests {
         test_into_entries_error(
             r#"
             path/to/target:
-              ~source: path/to/source
-              ~type: template
-              ~recursive: false
+              +source: path/to/source
+              +type: template
+              +recursive: false
               child1: path/to/child1
             "#,
             expects_unexpected_children("path/to/target".as_ref()),
         );
     }
+
+    #[test]
+    fn conditional_nodes() {
+        let profile = r#"
+            path/to/target1:
+              +source: path/to/source1
+              +when: { os: linux }
+              child1: path/to/child1
+              child2:
+                +source: path/to/child2
+                +when: { hostname: work-* }
+            path/to/target2:
+              +source: path/to/source2
+              +when: { os: macos }
+            "#;
+        let collect = |hostname: &str| {
+            let host = Host {
+                os: "linux".to_owned(),
+                hostname: hostname.to_owned(),
+                ..Default::default()
+            };
+            profile_from_str(profile)
+                .into_entries_with(&host)
+                .unwrap()
+                .iter()
+                .map(|(target, _)| target.to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            collect("work-laptop"),
+            [
+                "path/to/target1",
+                "path/to/target1/child1",
+                "path/to/target1/child2"
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(
+            collect("home"),
+            ["path/to/target1", "path/to/target1/child1"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn attribute_origins() {
+        let mut profile = profile_from_str(
+            r#"
+            path/to:
+              +private: true
+              +ignore: ["*.bak"]
+              target1:
+                +source: path/to/source1
+                +ignore: ["*.orig"]
+            "#,
+        );
+        profile.set_file("dbot.yaml".as_ref());
+        let mut import = profile_from_str(
+            r#"
+            path/to/target1:
+              +recursive: true
+            "#,
+        );
+        import.set_file("import.yaml".as_ref());
+        profile.merge(import);
+
+        let entries = profile.into_entries().unwrap();
+        let (node, attr) = entries.find("path/to/target1/file".as_ref()).unwrap();
+        assert_eq!(node, Path::new("path/to/target1"));
+        let origin = |file: &str, node: &str, kind| Origin {
+            file: Some(Rc::from(Path::new(file))),
+            node: node.into(),
+            kind,
+            location: None,
+        };
+        assert_eq!(
+            attr.origins.get("source"),
+            [origin("dbot.yaml", "path/to/target1", OriginKind::Own)]
+        );
+        assert_eq!(
+            attr.origins.get("private"),
+            [origin("dbot.yaml", "path/to", OriginKind::Inherited)]
+        );
+        assert_eq!(
+            attr.origins.get("recursive"),
+            [origin("import.yaml", "path/to/target1", OriginKind::Merged)]
+        );
+        assert_eq!(
+            attr.origins.get("ignore"),
+            [
+                origin("dbot.yaml", "path/to/target1", OriginKind::Own),
+                origin("dbot.yaml", "path/to", OriginKind::Inherited),
+            ]
+        );
+        assert!(attr.origins.get("type").is_empty());
+        assert_eq!(
+            attr.ignore.patterns().collect::<Vec<_>>(),
+            ["*.orig", "*.bak"]
+        );
+    }
+
+    #[test]
+    fn error_spans() {
+        let content = r#"data: {}
+profile:
+  path/to:
+    target1:
+      +source: path/to/source1
+      +type: link
+      child: <copy>
+"#;
+        #[derive(Deserialize)]
+        struct Document {
+            profile: Profile,
+        }
+        let mut profile = serde_yaml::from_str::<Document>(content).unwrap().profile;
+        profile.set_source("dbot.yaml".as_ref(), content, &["profile"]);
+        let err = profile.into_entries().unwrap_err();
+        assert_eq!(
+            err.span(),
+            Some(&error::Span {
+                file: "dbot.yaml".into(),
+                location: Location { line: 6, column: 7 },
+            })
+        );
+    }
+
+    #[test]
+    fn validate_profile() {
+        let profile = profile_from_str(
+            r#"
+            path/to:
+              +recusive: true
+              target1:
+                +source: path/to/source1
+                +ignore: []
+              target2: path/to/source2
+            "#,
+        );
+        let mut diagnostics = Diagnostics::default();
+        profile.validate(&mut diagnostics);
+        let errors = diagnostics.iter().collect::<Vec<_>>();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            errors[0],
+            crate::Error::UnknownAttribute(name, target, _)
+                if name == "+recusive" && target == Path::new("path/to")
+        ));
+        assert!(matches!(
+            errors[1],
+            crate::Error::EmptyIgnore(target, _) if target == Path::new("path/to/target1")
+        ));
+    }
+
+    #[test]
+    fn build_profile() {
+        let mut profile = profile_from_str(
+            r#"
+            path:
+              to/target1: path/to/source1
+            path/to/target1:
+              +type: link
+            path/to/target2: path/to/source2
+            "#,
+        );
+        let node = ProfileNode::from_source("path/to/source3".as_ref())
+            .unwrap()
+            .with_type(AttrType::Template)
+            .with_ignore(["*.bak"])
+            .unwrap()
+            .with_child(
+                "child".as_ref(),
+                ProfileNode::default().with_type(AttrType::Copy),
+            )
+            .unwrap();
+        let removed = profile
+            .insert("/path/./to/target1".as_ref(), node.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(removed.source(), Some(Path::new("path/to/source1")));
+        assert_eq!(removed.ty(), Some(AttrType::Link));
+        assert_eq!(profile.get("path/to/target1".as_ref()), Some(&node));
+        assert!(profile.remove("path/to/target2".as_ref()).is_some());
+        assert!(profile.remove("path/to/target2".as_ref()).is_none());
+        assert_eq!(
+            serde_yaml::to_string(&profile).unwrap(),
+            r#"path: {}
+path/to/target1:
+  +source: path/to/source3
+  +type: template
+  +ignore: '*.bak'
+  child: <copy>
+"#
+        );
+        assert!(ProfileNode::default().with_ignore(["a/**["]).is_err());
+    }
+
+    #[test]
+    fn mount_profile() {
+        let mut profile = profile_from_str(
+            r#"
+            init.lua: init.lua
+            lua:
+              +source: lua
+              +type: link
+            after: <copy>
+            colors:
+              dark.vim: <link>
+            "#,
+        );
+        profile
+            .mount("/.config/nvim/".as_ref(), "nvim/".as_ref())
+            .unwrap();
+        let entries = profile.into_entries().unwrap();
+        let entries = entries
+            .iter()
+            .map(|(target, attr)| {
+                (
+                    target.to_str().unwrap(),
+                    attr.source.to_str().unwrap(),
+                    attr.ty,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                (".config/nvim/after", "nvim/after", AttrType::Copy),
+                (
+                    ".config/nvim/colors/dark.vim",
+                    "nvim/colors/dark.vim",
+                    AttrType::Link
+                ),
+                (".config/nvim/init.lua", "nvim/init.lua", AttrType::Copy),
+                (".config/nvim/lua", "nvim/lua", AttrType::Link),
+            ]
+        );
+    }
 }
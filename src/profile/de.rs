@@ -1,6 +1,7 @@
 use super::{
     parse::{normalize_path, parse_attribute},
-    ProfileAttrBuilder, ProfileNode,
+    when::Conditions,
+    Location, Origin, Origins, ProfileAttrBuilder, ProfileNode,
 };
 use serde::{
     de::{Error as DeError, Visitor},
@@ -8,11 +9,73 @@ use serde::{
 };
 use std::{collections::HashMap, fmt, path::PathBuf, rc::Rc};
 use tracing::warn;
+use yaml_rust2::{
+    parser::{MarkedEventReceiver, Parser},
+    scanner::Marker,
+    Event,
+};
 
 fn deserialize_path_normalized<E: DeError>(v: &str) -> Result<PathBuf, E> {
     normalize_path(v).map_err(E::custom)
 }
 
+/// File mode in octal, e.g. `644`, `"0644"` or `0o644`. Integers written
+/// without the `0o` prefix are read as octal digits like `chmod` does.
+struct Mode(u32);
+
+struct ModeVisitor;
+
+impl<'de> Visitor<'de> for ModeVisitor {
+    type Value = Mode;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a file mode")
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+    where
+        E: DeError,
+    {
+        self.visit_str(&v.to_string())
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: DeError,
+    {
+        let digits = v.strip_prefix("0o").unwrap_or(v);
+        match u32::from_str_radix(digits, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(Mode(mode)),
+            _ => Err(E::custom(format!("invalid file mode '{v}'"))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Mode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // Reads plain scalars as they are written, since YAML parses both
+        // `600` and `0o600` into integers.
+        deserializer.deserialize_str(ModeVisitor)
+    }
+}
+
+/// Names of all attributes recognized in profile nodes, without the `+` prefix.
+pub const ATTRIBUTES: &[&str] = &[
+    "source",
+    "type",
+    "recursive",
+    "ignore",
+    "when",
+    "mode",
+    "executable",
+    "private",
+    "preserve_mode",
+    "relative",
+];
+
 struct ProfileNodeVistor;
 
 impl<'de> Visitor<'de> for ProfileNodeVistor {
@@ -26,8 +89,10 @@ impl<'de> Visitor<'de> for ProfileNodeVistor {
     where
         E: DeError,
     {
+        let mut attr = parse_attribute(v).map_err(E::custom)?;
+        attr.origins = Origins::own(&attr);
         Ok(ProfileNode {
-            attr: parse_attribute(v).map_err(E::custom)?,
+            attr,
             ..Default::default()
         })
     }
@@ -38,16 +103,25 @@ impl<'de> Visitor<'de> for ProfileNodeVistor {
     {
         let mut attr = ProfileAttrBuilder::default();
         let mut children = HashMap::with_capacity(map.size_hint().unwrap_or_default());
+        let mut unknown = Vec::new();
         while let Some(key) = map.next_key::<&str>()? {
             if let Some(attr_str) = key.strip_prefix('+') {
+                // Attributes handled here must be listed in `ATTRIBUTES`.
                 match attr_str {
                     "source" => attr.source = Some(deserialize_path_normalized(map.next_value()?)?),
                     "type" => attr.ty = Some(map.next_value()?),
                     "recursive" => attr.recursive = Some(map.next_value()?),
                     "ignore" => attr.ignore = Some(Rc::new(map.next_value()?)),
+                    "when" => attr.when = Some(Rc::new(map.next_value::<Conditions>()?)),
+                    "mode" => attr.mode = Some(map.next_value::<Mode>()?.0),
+                    "executable" => attr.executable = Some(map.next_value()?),
+                    "private" => attr.private = Some(map.next_value()?),
+                    "preserve_mode" => attr.preserve_mode = Some(map.next_value()?),
+                    "relative" => attr.relative = Some(map.next_value()?),
                     _ => {
                         warn!("Undefined attribute '{}'", key);
                         map.next_value::<serde_yaml::Value>()?;
+                        unknown.push((key.to_owned(), Origin::own()));
                     }
                 }
             } else {
@@ -56,7 +130,12 @@ impl<'de> Visitor<'de> for ProfileNodeVistor {
                 children.insert(dest, node);
             }
         }
-        Ok(ProfileNode { attr, children })
+        attr.origins = Origins::own(&attr);
+        Ok(ProfileNode {
+            attr,
+            children,
+            unknown,
+        })
     }
 }
 
@@ -69,10 +148,141 @@ impl<'de> Deserialize<'de> for ProfileNode {
     }
 }
 
+/// Locations of mapping keys in a YAML document, which serde doesn't provide.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub(super) struct KeyLocations(pub Vec<(String, Location, KeyLocations)>);
+
+#[derive(Default)]
+struct EventCollector(Vec<(Event, Marker)>);
+
+impl MarkedEventReceiver for EventCollector {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        self.0.push((ev, mark));
+    }
+}
+
+impl KeyLocations {
+    /// Returns `None` if `content` is not a valid YAML document.
+    pub fn parse(content: &str) -> Option<Self> {
+        let mut collector = EventCollector::default();
+        Parser::new_from_str(content)
+            .load(&mut collector, false)
+            .ok()?;
+        let mut events = collector.0.into_iter().map(|(ev, mark)| {
+            let location = Location {
+                line: mark.line(),
+                column: mark.col() + 1,
+            };
+            (ev, location)
+        });
+        // Skip events until the root node.
+        for (ev, _) in events.by_ref() {
+            if ev == Event::DocumentStart {
+                break;
+            }
+        }
+        Some(Self::parse_node(&mut events))
+    }
+
+    fn parse_node(events: &mut impl Iterator<Item = (Event, Location)>) -> Self {
+        match events.next() {
+            Some((Event::MappingStart(..), _)) => {
+                let mut keys = Vec::new();
+                while let Some((ev, location)) = events.next() {
+                    match ev {
+                        Event::MappingEnd => break,
+                        Event::Scalar(key, ..) => {
+                            keys.push((key, location, Self::parse_node(events)))
+                        }
+                        // Complex keys are never used in profiles.
+                        Event::MappingStart(..) | Event::SequenceStart(..) => {
+                            Self::skip_collection(events);
+                            Self::parse_node(events);
+                        }
+                        _ => {
+                            Self::parse_node(events);
+                        }
+                    }
+                }
+                KeyLocations(keys)
+            }
+            Some((Event::SequenceStart(..), _)) => {
+                Self::skip_collection(events);
+                Self::default()
+            }
+            _ => Self::default(),
+        }
+    }
+
+    /// Skips events until the end of the current collection.
+    fn skip_collection(events: &mut impl Iterator<Item = (Event, Location)>) {
+        let mut depth = 1;
+        for (ev, _) in events {
+            match ev {
+                Event::MappingStart(..) | Event::SequenceStart(..) => depth += 1,
+                Event::MappingEnd | Event::SequenceEnd => depth -= 1,
+                _ => (),
+            }
+            if depth == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Returns the location of the key nested in `keys`.
+    pub fn locate(&self, keys: &[&str]) -> Option<Location> {
+        let (last, keys) = keys.split_last()?;
+        let mut locations = self;
+        for key in keys {
+            locations = locations.get(key)?;
+        }
+        locations
+            .0
+            .iter()
+            .find(|(k, ..)| k == last)
+            .map(|(_, location, _)| *location)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&KeyLocations> {
+        self.0
+            .iter()
+            .find(|(k, ..)| k == key)
+            .map(|(_, _, children)| children)
+    }
+}
+
+impl ProfileNode {
+    /// Records locations of attributes, those defined in the shorthand form
+    /// are located at `location` of the node.
+    pub(super) fn locate(&mut self, keys: &KeyLocations, location: Option<Location>) {
+        for (key, location, children) in keys.0.iter() {
+            if let Some(name) = key.strip_prefix('+') {
+                for origin in self.attr.origins.get_mut(name) {
+                    origin.location = Some(*location);
+                }
+                for (_, origin) in self.unknown.iter_mut().filter(|(k, _)| k == key) {
+                    origin.location = Some(*location);
+                }
+            } else if let Some(child) = normalize_path(key)
+                .ok()
+                .and_then(|path| self.children.get_mut(&path))
+            {
+                child.locate(children, Some(*location));
+            }
+        }
+        if let Some(location) = location {
+            self.attr.origins.for_each_mut(|origin| {
+                origin.location.get_or_insert(location);
+            });
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::profile::{path_only_attr, AttrType};
+    use std::path::Path;
 
     fn path_only_node<T>(source: T) -> ProfileNode
     where
@@ -88,7 +298,7 @@ mod tests {
     fn normalized_path_attributes() {
         let node = serde_yaml::from_str::<ProfileNode>(
             r#"
-            ~source: skip/../path/to/root
+            +source: skip/../path/to/root
             /path/to/./target1: ../path/to/source1
             "#,
         )
@@ -98,6 +308,7 @@ mod tests {
             children: [("path/to/target1".into(), path_only_node("path/to/source1"))]
                 .into_iter()
                 .collect(),
+            ..Default::default()
         };
         assert_eq!(node, expected);
     }
@@ -106,10 +317,10 @@ mod tests {
     fn deserialize_profile_attr() {
         let node = serde_yaml::from_str::<ProfileNode>(
             r#"
-            ~source: path/to/source
-            ~type: link
-            ~undefined_attr: ...
-            ~recursive: true
+            +source: path/to/source
+            +type: link
+            +undefined_attr: ...
+            +recursive: true
             "#,
         )
         .unwrap();
@@ -117,10 +328,12 @@ mod tests {
             source: Some("path/to/source".into()),
             ty: Some(AttrType::Link),
             recursive: Some(true),
-            ignore: None,
+            ..Default::default()
         };
         assert_eq!(node.attr, attr);
         assert!(node.children.is_empty());
+        assert_eq!(node.unknown.len(), 1);
+        assert_eq!(node.unknown[0].0, "+undefined_attr");
     }
 
     #[test]
@@ -129,7 +342,7 @@ mod tests {
             r#"
             target1: path/to/source1
             target2:
-              ~source: path/to/source2
+              +source: path/to/source2
             "#,
         )
         .unwrap();
@@ -146,4 +359,37 @@ mod tests {
         };
         assert_eq!(node, expected);
     }
+
+    #[test]
+    fn deserialize_mode_digits() {
+        for (mode, expected) in [("600", 0o600), ("644", 0o644), ("\"600\"", 0o600)] {
+            let node = serde_yaml::from_str::<ProfileNode>(&format!("+mode: {mode}")).unwrap();
+            assert_eq!(node.attr.mode, Some(expected), "{mode}");
+        }
+        assert!(serde_yaml::from_str::<ProfileNode>("+mode: 1e3").is_err());
+    }
+
+    #[test]
+    fn deserialize_permissions() {
+        let node = serde_yaml::from_str::<ProfileNode>(
+            r#"
+            +mode: 0600
+            +executable: true
+            +private: true
+            child1:
+              +mode: 0o755
+            child2:
+              +mode: "0o644"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(node.attr.mode, Some(0o600));
+        assert_eq!(node.attr.executable, Some(true));
+        assert_eq!(node.attr.private, Some(true));
+        assert_eq!(node.children[Path::new("child1")].attr.mode, Some(0o755));
+        assert_eq!(node.children[Path::new("child2")].attr.mode, Some(0o644));
+        assert!(serde_yaml::from_str::<ProfileNode>("+mode: 0800").is_err());
+        assert!(serde_yaml::from_str::<ProfileNode>("+mode: -600").is_err());
+        assert!(serde_yaml::from_str::<ProfileNode>("+mode: 0o17777").is_err());
+    }
 }
@@ -0,0 +1,175 @@
+use super::ProfileAttrBuilder;
+use crate::error::Span;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// Attributes whose values are extended rather than overridden.
+const EXTENDED: &[&str] = &["ignore"];
+
+/// How an attribute ends up on a node.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OriginKind {
+    /// Defined on the node itself.
+    Own,
+    /// Inherited from an ancestor node.
+    Inherited,
+    /// Defined on the same node in a merged profile, e.g. an import.
+    Merged,
+}
+
+/// Line and column in a profile file, both starting from 1.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Where an attribute is defined.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Origin {
+    /// Profile file which defines the attribute, if known.
+    pub file: Option<Rc<Path>>,
+    /// Path of the node which defines the attribute.
+    pub node: PathBuf,
+    pub kind: OriginKind,
+    /// Location of the attribute in the file, if known.
+    pub location: Option<Location>,
+}
+
+impl Origin {
+    pub(super) fn own() -> Self {
+        Origin {
+            file: None,
+            node: PathBuf::new(),
+            kind: OriginKind::Own,
+            location: None,
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        Some(Span {
+            file: self.file.as_deref()?.to_owned(),
+            location: self.location?,
+        })
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.kind {
+            OriginKind::Own => "own",
+            OriginKind::Inherited => "inherited",
+            OriginKind::Merged => "merged",
+        })?;
+        if self.kind == OriginKind::Inherited {
+            write!(f, " from '{}'", self.node.display())?;
+        }
+        match (self.file.as_ref(), self.location) {
+            (Some(file), Some(Location { line, column })) => {
+                write!(f, " in '{}:{line}:{column}'", file.display())?
+            }
+            (Some(file), None) => write!(f, " in '{}'", file.display())?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Origins of attributes keyed by their names, e.g. `source` or `type`.
+///
+/// Origins are not part of attribute values, hence they never affect the
+/// equality of attributes.
+#[derive(Clone, Debug, Default)]
+pub struct Origins(BTreeMap<&'static str, Vec<Origin>>);
+
+impl PartialEq for Origins {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Origins {}
+
+impl Origins {
+    /// Returns origins of attribute `name`, an empty slice means the default
+    /// value is used.
+    pub fn get(&self, name: &str) -> &[Origin] {
+        self.0.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Returns where attribute `name` is defined first, if known.
+    pub fn span(&self, name: &str) -> Option<Span> {
+        self.get(name).first().and_then(Origin::span)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[Origin])> {
+        self.0
+            .iter()
+            .map(|(name, origins)| (*name, origins.as_slice()))
+    }
+
+    /// Creates origins for all attributes defined in `attr`.
+    pub(super) fn own(attr: &ProfileAttrBuilder) -> Self {
+        let defined = [
+            ("source", attr.source.is_some()),
+            ("type", attr.ty.is_some()),
+            ("recursive", attr.recursive.is_some()),
+            ("ignore", attr.ignore.is_some()),
+            ("mode", attr.mode.is_some()),
+            ("executable", attr.executable.is_some()),
+            ("private", attr.private.is_some()),
+            ("preserve_mode", attr.preserve_mode.is_some()),
+            ("relative", attr.relative.is_some()),
+        ];
+        let origin = Origin::own();
+        Origins(
+            defined
+                .into_iter()
+                .filter(|(_, defined)| *defined)
+                .map(|(name, _)| (name, vec![origin.clone()]))
+                .collect(),
+        )
+    }
+
+    pub(super) fn get_mut(&mut self, name: &str) -> &mut [Origin] {
+        self.0
+            .get_mut(name)
+            .map(Vec::as_mut_slice)
+            .unwrap_or_default()
+    }
+
+    pub(super) fn for_each_mut(&mut self, mut f: impl FnMut(&mut Origin)) {
+        self.0.values_mut().flatten().for_each(&mut f);
+    }
+
+    /// Takes origins of attributes defined in `other`.
+    pub(super) fn merge(&mut self, other: Self) {
+        for (name, origins) in other.0 {
+            let this = self.0.entry(name).or_default();
+            if !EXTENDED.contains(&name) {
+                this.clear();
+            }
+            this.extend(origins);
+        }
+    }
+
+    /// Takes origins of attributes inherited from `parent`.
+    pub(super) fn inherit(&mut self, parent: &Self) {
+        for (name, origins) in parent.0.iter() {
+            if !EXTENDED.contains(name) && self.0.contains_key(name) {
+                continue;
+            }
+            self.0
+                .entry(name)
+                .or_default()
+                .extend(origins.iter().map(|origin| Origin {
+                    kind: OriginKind::Inherited,
+                    ..origin.clone()
+                }));
+        }
+    }
+}
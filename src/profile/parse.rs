@@ -1,5 +1,8 @@
 use super::{AttrType, ProfileAttrBuilder};
-use std::path::{Component, Path, PathBuf};
+use std::{
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
 use thisctx::IntoError;
 
 mod error {
@@ -12,7 +15,7 @@ mod error {
         PathStartsWithPrefix(&'a str),
         #[error("profile type must end with '>'")]
         TypeMustEndWithGt,
-        #[error("unknown attribute type '{0}'")]
+        #[error("unknown attribute type '{0}', expected copy, link or template")]
         UnknownType(&'a str),
     }
 }
@@ -21,7 +24,7 @@ type Result<'a, T> = std::result::Result<T, error::Error<'a>>;
 
 macro_rules! define_ty {
     ($($name:ident => $variant:ident,)*) => {
-        const ALL_TYPES: &[(&str, AttrType)] = &[$((stringify!($name), AttrType::$variant),)*];
+        pub(super) const ALL_TYPES: &[(&str, AttrType)] = &[$((stringify!($name), AttrType::$variant),)*];
     };
 }
 
@@ -31,13 +34,26 @@ define_ty! {
     template => Template,
 }
 
-pub(super) fn parse_attribute(s: &str) -> Result<ProfileAttrBuilder> {
+impl FromStr for AttrType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match ALL_TYPES.binary_search_by(|(ty, _)| ty.cmp(&s)) {
+            Ok(i) => Ok(ALL_TYPES[i].1),
+            Err(_) => Err(format!(
+                "unknown attribute type '{s}', expected copy, link or template"
+            )),
+        }
+    }
+}
+
+pub(super) fn parse_attribute(s: &str) -> Result<'_, ProfileAttrBuilder> {
     Ok(if s.starts_with('<') {
         if s.ends_with('>') {
             let ty = &s[1..s.len() - 1];
             ProfileAttrBuilder {
-                ty: if let Ok(i) = ALL_TYPES.binary_search_by(|(s, _)| s.cmp(&ty)) {
-                    Some(ALL_TYPES[i].1)
+                ty: if let Ok(ty) = ty.parse() {
+                    Some(ty)
                 } else {
                     return error::UnknownType(s).fail();
                 },
@@ -54,7 +70,7 @@ pub(super) fn parse_attribute(s: &str) -> Result<ProfileAttrBuilder> {
     })
 }
 
-pub(crate) fn normalize_path(path: &str) -> Result<PathBuf> {
+pub(crate) fn normalize_path(path: &str) -> Result<'_, PathBuf> {
     let mut buf = PathBuf::new();
     for compo in Path::new(path).components() {
         match compo {
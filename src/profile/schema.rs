@@ -0,0 +1,280 @@
+use super::parse::ALL_TYPES;
+use serde_json::{json, Map, Value};
+
+/// Schema of an attribute and values used to check it against the parser.
+struct Attribute {
+    name: &'static str,
+    schema: Value,
+}
+
+fn attributes() -> Vec<Attribute> {
+    let attr = |name, schema| Attribute { name, schema };
+    vec![
+        attr(
+            "source",
+            json!({
+                "description": "Source path relative to the source directory.",
+                "type": "string",
+                "examples": ["path/to/source"],
+            }),
+        ),
+        attr(
+            "type",
+            json!({
+                "description": "How the target is created from its source.",
+                "$ref": "#/definitions/type",
+                "examples": ["link"],
+            }),
+        ),
+        attr(
+            "recursive",
+            json!({
+                "description": "Create targets for each file in the source directory.",
+                "type": "boolean",
+                "examples": [true],
+            }),
+        ),
+        attr(
+            "ignore",
+            json!({
+                "description": "Glob patterns of sources to skip, extended by children.",
+                "$ref": "#/definitions/patternSet",
+                "examples": [["*.bak", "**/.git"]],
+            }),
+        ),
+        attr(
+            "when",
+            json!({
+                "description": "Conditions on the current machine to create the target.",
+                "anyOf": [
+                    { "$ref": "#/definitions/condition" },
+                    { "type": "array", "items": { "$ref": "#/definitions/condition" } },
+                ],
+                "examples": [
+                    { "os": "linux", "env": "WAYLAND_DISPLAY" },
+                    [{ "os": "linux" }, { "hostname": "work-*" }],
+                ],
+            }),
+        ),
+        attr(
+            "mode",
+            json!({
+                "description": "Permission bits of the target in octal, where integers without the `0o` prefix are read as octal digits like `chmod`.",
+                "$ref": "#/definitions/mode",
+                "examples": ["0o600", 644],
+            }),
+        ),
+        attr(
+            "executable",
+            json!({
+                "description": "Make the target executable.",
+                "type": "boolean",
+                "examples": [true],
+            }),
+        ),
+        attr(
+            "private",
+            json!({
+                "description": "Make the target readable by the owner only.",
+                "type": "boolean",
+                "examples": [true],
+            }),
+        ),
+        attr(
+            "preserve_mode",
+            json!({
+                "description": "Copy permissions of the source to the target.",
+                "type": "boolean",
+                "examples": [false],
+            }),
+        ),
+        attr(
+            "relative",
+            json!({
+                "description": "Create symlinks with paths relative to the target.",
+                "type": "boolean",
+                "examples": [true],
+            }),
+        ),
+    ]
+}
+
+fn pattern_set() -> Value {
+    json!({
+        "description": "A glob pattern or a list of glob patterns.",
+        "anyOf": [
+            { "type": "string" },
+            { "type": "array", "items": { "type": "string" } },
+        ],
+    })
+}
+
+fn node() -> Value {
+    let types = ALL_TYPES
+        .iter()
+        .map(|(ty, _)| *ty)
+        .collect::<Vec<_>>()
+        .join("|");
+    let properties = attributes()
+        .into_iter()
+        .map(|attr| (format!("+{}", attr.name), attr.schema))
+        .collect::<Map<_, _>>();
+    let names = properties.keys().cloned().collect::<Vec<_>>();
+    json!({
+        "anyOf": [
+            {
+                "description": "A source path, or a type like `<link>` inheriting the source.",
+                "type": "string",
+                "anyOf": [
+                    { "pattern": format!("^<({types})>$") },
+                    { "not": { "pattern": "^<" } },
+                ],
+            },
+            {
+                "description": "Attributes prefixed with `+` and children keyed by target paths.",
+                "type": "object",
+                "properties": properties,
+                "propertyNames": {
+                    "anyOf": [{ "not": { "pattern": "^\\+" } }, { "enum": names }],
+                },
+                "additionalProperties": { "$ref": "#/definitions/node" },
+            },
+        ],
+    })
+}
+
+/// Returns the JSON Schema of profile files, which helps editors to complete
+/// and validate them.
+pub fn schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "dbot profile",
+        "type": "object",
+        "properties": {
+            "import": {
+                "description": "Profile files to merge, relative to the importing file.",
+                "type": "array",
+                "items": { "$ref": "#/definitions/import" },
+            },
+            "data": {
+                "description": "Data available to templates as `data`.",
+                "type": "object",
+            },
+            "profile": {
+                "description": "Targets relative to the target directory.",
+                "$ref": "#/definitions/node",
+            },
+        },
+        "additionalProperties": false,
+        "definitions": {
+            "import": {
+                "anyOf": [
+                    { "description": "A path or a glob pattern.", "type": "string" },
+                    {
+                        "type": "object",
+                        "properties": {
+                            "path": {
+                                "description": "A path or a glob pattern.",
+                                "type": "string",
+                            },
+                            "target": {
+                                "description": "Prefix of targets in the imported profile.",
+                                "type": "string",
+                            },
+                            "source": {
+                                "description": "Prefix of sources in the imported profile.",
+                                "type": "string",
+                            },
+                            "when": {
+                                "description": "Conditions on the current machine to import.",
+                                "anyOf": [
+                                    { "$ref": "#/definitions/condition" },
+                                    {
+                                        "type": "array",
+                                        "items": { "$ref": "#/definitions/condition" },
+                                    },
+                                ],
+                            },
+                        },
+                        "required": ["path"],
+                        "additionalProperties": false,
+                    },
+                ],
+            },
+            "node": node(),
+            "type": {
+                "enum": ALL_TYPES.iter().map(|(ty, _)| *ty).collect::<Vec<_>>(),
+            },
+            "patternSet": pattern_set(),
+            "mode": {
+                "anyOf": [
+                    { "type": "integer", "minimum": 0, "maximum": 7777 },
+                    { "type": "string", "pattern": "^(0o)?0*[0-7]{1,4}$" },
+                ],
+            },
+            "condition": {
+                "type": "object",
+                "properties": {
+                    "os": { "$ref": "#/definitions/patternSet" },
+                    "arch": { "$ref": "#/definitions/patternSet" },
+                    "hostname": { "$ref": "#/definitions/patternSet" },
+                    "user": { "$ref": "#/definitions/patternSet" },
+                    "env": {
+                        "description": "Variables which must be set, or patterns of their values.",
+                        "anyOf": [
+                            { "type": "string" },
+                            { "type": "array", "items": { "type": "string" } },
+                            {
+                                "type": "object",
+                                "additionalProperties": { "$ref": "#/definitions/patternSet" },
+                            },
+                        ],
+                    },
+                },
+                "additionalProperties": false,
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{de::ATTRIBUTES, AttrType, ProfileNode};
+
+    #[test]
+    fn schema_attributes_in_sync() {
+        let mut names = attributes()
+            .iter()
+            .map(|attr| attr.name)
+            .collect::<Vec<_>>();
+        let mut expected = ATTRIBUTES.to_vec();
+        names.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(names, expected);
+        // Examples must be accepted by the parser as known attributes, which
+        // are written in YAML as profiles are.
+        for attr in attributes() {
+            for example in attr.schema["examples"].as_array().unwrap() {
+                let value = json!({ format!("+{}", attr.name): example });
+                let yaml = serde_yaml::to_string(&value).unwrap();
+                let node = serde_yaml::from_str::<ProfileNode>(&yaml)
+                    .unwrap_or_else(|e| panic!("invalid example of '{}': {e}", attr.name));
+                assert!(node.unknown.is_empty(), "unknown attribute '{}'", attr.name);
+            }
+        }
+    }
+
+    #[test]
+    fn schema_types_in_sync() {
+        let schema = schema();
+        let types = schema["definitions"]["type"]["enum"].as_array().unwrap();
+        assert_eq!(types.len(), 3);
+        for ty in types {
+            let ty = ty.as_str().unwrap();
+            ty.parse::<AttrType>().unwrap();
+            let node = serde_yaml::from_str::<ProfileNode>(&format!("<{ty}>")).unwrap();
+            assert_eq!(node.attr.ty, Some(ty.parse().unwrap()));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use super::{ProfileAttrBuilder, ProfileNode};
+use serde::{
+    ser::{Error as SerError, SerializeMap},
+    Serialize, Serializer,
+};
+use std::path::Path;
+
+fn path_str<E: SerError>(path: &Path) -> Result<&str, E> {
+    path.to_str()
+        .ok_or_else(|| E::custom(format!("non-UTF-8 path '{}'", path.display())))
+}
+
+/// Returns the shorthand form of `attr`, which is either a plain source path
+/// or a type like `<link>`.
+fn shorthand<E: SerError>(attr: &ProfileAttrBuilder) -> Result<Option<String>, E> {
+    // Origins never affect the equality of attributes.
+    let only = |attr2: ProfileAttrBuilder| *attr == attr2;
+    if let Some(source) = attr.source.as_ref() {
+        let source = path_str(source)?;
+        if !source.starts_with('<')
+            && only(ProfileAttrBuilder {
+                source: attr.source.clone(),
+                ..Default::default()
+            })
+        {
+            return Ok(Some(source.to_owned()));
+        }
+    } else if let Some(ty) = attr.ty {
+        if only(ProfileAttrBuilder {
+            ty: Some(ty),
+            ..Default::default()
+        }) {
+            return Ok(Some(format!("<{ty}>")));
+        }
+    }
+    Ok(None)
+}
+
+/// Writes nodes in the canonical form, where undefined attributes are dropped
+/// and children are sorted by their targets.
+impl Serialize for ProfileNode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let attr = &self.attr;
+        if self.children.is_empty() {
+            if let Some(shorthand) = shorthand(attr)? {
+                return serializer.serialize_str(&shorthand);
+            }
+        }
+        let mut map = serializer.serialize_map(None)?;
+        if let Some(source) = attr.source.as_ref() {
+            map.serialize_entry("+source", path_str(source)?)?;
+        }
+        if let Some(ty) = attr.ty {
+            map.serialize_entry("+type", &ty)?;
+        }
+        if let Some(recursive) = attr.recursive {
+            map.serialize_entry("+recursive", &recursive)?;
+        }
+        if let Some(ignore) = attr.ignore.as_ref() {
+            map.serialize_entry("+ignore", &ignore.builder)?;
+        }
+        if let Some(when) = attr.when.as_ref() {
+            map.serialize_entry("+when", when.as_ref())?;
+        }
+        if let Some(mode) = attr.mode {
+            map.serialize_entry("+mode", &format!("0o{mode:o}"))?;
+        }
+        if let Some(executable) = attr.executable {
+            map.serialize_entry("+executable", &executable)?;
+        }
+        if let Some(private) = attr.private {
+            map.serialize_entry("+private", &private)?;
+        }
+        if let Some(preserve_mode) = attr.preserve_mode {
+            map.serialize_entry("+preserve_mode", &preserve_mode)?;
+        }
+        if let Some(relative) = attr.relative {
+            map.serialize_entry("+relative", &relative)?;
+        }
+        for (target, child) in self.sorted_children() {
+            map.serialize_entry(path_str(target)?, child)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::AttrType;
+
+    fn canonical(s: &str) -> String {
+        let node = serde_yaml::from_str::<ProfileNode>(s).unwrap();
+        serde_yaml::to_string(&node).unwrap()
+    }
+
+    #[test]
+    fn serialize_shorthand() {
+        assert_eq!(canonical("path/to/source"), "path/to/source\n");
+        assert_eq!(canonical("+source: path/to/source"), "path/to/source\n");
+        assert_eq!(canonical("+type: link"), "<link>\n");
+        assert_eq!(
+            canonical("+source: path/to/source\n+type: link"),
+            "+source: path/to/source\n+type: link\n"
+        );
+    }
+
+    #[test]
+    fn serialize_profile_node() {
+        let s = r#"
+            target2:
+              +mode: 0600
+              +ignore: ["*.bak"]
+              +when: { os: linux }
+            target1:
+              +type: template
+              +undefined_attr: ...
+              child: path/to/source1
+            "#;
+        let expected = r#"target1:
+  +type: template
+  child: path/to/source1
+target2:
+  +ignore: '*.bak'
+  +when:
+    os: linux
+  +mode: '0o600'
+"#;
+        assert_eq!(canonical(s), expected);
+        // Writes nodes which are read back as is.
+        let mut node = serde_yaml::from_str::<ProfileNode>(s).unwrap();
+        node.children
+            .values_mut()
+            .for_each(|child| child.unknown.clear());
+        let read = serde_yaml::from_str::<ProfileNode>(&canonical(s)).unwrap();
+        assert_eq!(read, node);
+        assert_eq!(
+            read.children[Path::new("target1")].attr.ty,
+            Some(AttrType::Template)
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use crate::pattern::PatternSetBuilder;
+use serde::{
+    de::{value::MapAccessDeserializer, Visitor},
+    Deserialize, Serialize,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+/// Properties of the current machine which conditions are evaluated against.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Host {
+    pub os: String,
+    pub arch: String,
+    pub hostname: String,
+    pub user: String,
+    pub env: HashMap<String, String>,
+}
+
+impl Host {
+    pub fn current() -> Self {
+        Host {
+            os: std::env::consts::OS.to_owned(),
+            arch: std::env::consts::ARCH.to_owned(),
+            hostname: whoami::fallible::hostname().unwrap_or_default(),
+            user: whoami::username(),
+            env: std::env::vars().collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(
+    untagged,
+    expecting = "a name, a list of names or a mapping of patterns"
+)]
+enum EnvCondition {
+    Name(String),
+    Names(Vec<String>),
+    Values(BTreeMap<String, PatternSetBuilder>),
+}
+
+/// Conditions defined in a `when` attribute, all of which must be satisfied.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Condition {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    os: Option<PatternSetBuilder>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    arch: Option<PatternSetBuilder>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<PatternSetBuilder>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<PatternSetBuilder>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env: Option<EnvCondition>,
+}
+
+fn is_match(patterns: Option<&PatternSetBuilder>, value: &str) -> bool {
+    match patterns {
+        Some(patterns) => patterns
+            .build()
+            .map(|set| set.is_match(value))
+            .unwrap_or_default(),
+        None => true,
+    }
+}
+
+impl Condition {
+    pub fn matches(&self, host: &Host) -> bool {
+        let is_set = |name: &String| host.env.get(name).is_some_and(|val| !val.is_empty());
+        is_match(self.os.as_ref(), &host.os)
+            && is_match(self.arch.as_ref(), &host.arch)
+            && is_match(self.hostname.as_ref(), &host.hostname)
+            && is_match(self.user.as_ref(), &host.user)
+            && match &self.env {
+                Some(EnvCondition::Name(name)) => is_set(name),
+                Some(EnvCondition::Names(names)) => names.iter().all(is_set),
+                Some(EnvCondition::Values(values)) => values.iter().all(|(name, patterns)| {
+                    host.env
+                        .get(name)
+                        .is_some_and(|val| is_match(Some(patterns), val))
+                }),
+                None => true,
+            }
+    }
+}
+
+/// A set of conditions, all of which must be satisfied, e.g. those collected
+/// from a node and all its ancestors.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Conditions(pub(crate) Vec<Condition>);
+
+impl Conditions {
+    pub fn matches(&self, host: &Host) -> bool {
+        self.0.iter().all(|cond| cond.matches(host))
+    }
+}
+
+struct ConditionsVisitor;
+
+impl<'de> Visitor<'de> for ConditionsVisitor {
+    type Value = Conditions;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a condition or a list of conditions")
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        let cond = Condition::deserialize(MapAccessDeserializer::new(map))?;
+        Ok(Conditions(vec![cond]))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut conds = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(cond) = seq.next_element()? {
+            conds.push(cond);
+        }
+        Ok(Conditions(conds))
+    }
+}
+
+impl<'de> Deserialize<'de> for Conditions {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ConditionsVisitor)
+    }
+}
+
+/// Writes a single condition as is, which is the common case.
+impl Serialize for Conditions {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self.0.as_slice() {
+            [cond] => cond.serialize(serializer),
+            conds => conds.serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> Host {
+        Host {
+            os: "linux".to_owned(),
+            arch: "x86_64".to_owned(),
+            hostname: "work-laptop".to_owned(),
+            user: "dbot".to_owned(),
+            env: [("CI".to_owned(), "true".to_owned())].into_iter().collect(),
+        }
+    }
+
+    fn matches(s: &str) -> bool {
+        serde_yaml::from_str::<Condition>(s)
+            .unwrap()
+            .matches(&host())
+    }
+
+    #[test]
+    fn match_condition() {
+        assert!(matches("{}"));
+        assert!(matches("{ os: linux, hostname: work-* }"));
+        assert!(matches("{ os: [macos, linux], env: CI }"));
+        assert!(matches("{ env: { CI: 'true' } }"));
+        assert!(!matches("{ os: macos }"));
+        assert!(!matches("{ hostname: home-* }"));
+        assert!(!matches("{ env: [CI, HOME] }"));
+        assert!(!matches("{ env: { CI: 'false' } }"));
+    }
+
+    #[test]
+    fn list_of_conditions() {
+        let conds = serde_yaml::from_str::<Conditions>("[{ os: linux }, { env: HOME }]").unwrap();
+        assert_eq!(conds.0.len(), 2);
+        assert!(!conds.matches(&host()));
+        let conds = serde_yaml::from_str::<Conditions>("{ os: linux }").unwrap();
+        assert_eq!(serde_yaml::to_string(&conds).unwrap(), "os: linux\n");
+    }
+
+    #[test]
+    fn unknown_condition() {
+        assert!(serde_yaml::from_str::<Condition>("{ hostnmae: work }").is_err());
+    }
+}
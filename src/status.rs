@@ -0,0 +1,206 @@
+use crate::{
+    apply::{expected_contents, TemplateRenderer},
+    compile::{CompiledEntries, CompiledProfile},
+    error,
+    profile::AttrType,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use thisctx::WithContext;
+use time::OffsetDateTime;
+
+fn hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// States of a target file recorded when it is applied.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Fingerprint {
+    /// SHA-256 of the file contents, or of the original path for symlinks.
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<OffsetDateTime>,
+}
+
+impl Fingerprint {
+    /// Returns `None` if `path` doesn't exist.
+    pub fn of(path: &Path) -> error::Result<Option<Self>> {
+        let metadata = match path.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context(error::IoFailed(path)),
+        };
+        let hash = if metadata.is_symlink() {
+            let original = std::fs::read_link(path).context(error::IoFailed(path))?;
+            self::hash(original.to_string_lossy().as_bytes())
+        } else if metadata.is_file() {
+            self::hash(&std::fs::read(path).context(error::IoFailed(path))?)
+        } else {
+            self::hash(&[])
+        };
+        #[cfg(unix)]
+        let mode = if metadata.is_symlink() {
+            None
+        } else {
+            use std::os::unix::fs::PermissionsExt;
+            Some(metadata.permissions().mode() & 0o7777)
+        };
+        #[cfg(not(unix))]
+        let mode = None;
+        Ok(Some(Fingerprint {
+            hash,
+            mode,
+            mtime: metadata.modified().ok().map(OffsetDateTime::from),
+        }))
+    }
+
+    /// Checks whether two fingerprints have the same contents and mode, the
+    /// modification time is ignored.
+    pub fn same_contents(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.mode == other.mode
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Status {
+    /// Neither the target nor its source has changed since last applied.
+    Unchanged,
+    /// The target has been modified since last applied.
+    ModifiedInTarget,
+    /// The source has been modified since last applied.
+    ModifiedInSource,
+    /// The target has been removed.
+    Missing,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Status::Unchanged => "unchanged",
+            Status::ModifiedInTarget => "modified-in-target",
+            Status::ModifiedInSource => "modified-in-source",
+            Status::Missing => "missing",
+        })
+    }
+}
+
+/// Returns the hash that the target of `profile` is expected to have, or
+/// `None` if the source no longer exists.
+fn expected_hash<R: TemplateRenderer>(
+    renderer: &mut R,
+    target: &Path,
+    profile: &CompiledProfile,
+) -> error::Result<Option<String>>
+where
+    R::Err: 'static + std::error::Error + Send + Sync,
+{
+    let source = &profile.source;
+    if profile.ty == AttrType::Link {
+        let original = profile.link_original(target);
+        return Ok(Some(hash(original.to_string_lossy().as_bytes())));
+    }
+    if !source.exists() {
+        return Ok(None);
+    }
+    Ok(Some(hash(&expected_contents(renderer, target, profile)?)))
+}
+
+/// Compares managed targets with the fingerprints recorded when they were
+/// applied.
+pub fn status<R: TemplateRenderer>(
+    renderer: &mut R,
+    entries: &CompiledEntries,
+    fingerprints: &BTreeMap<PathBuf, Fingerprint>,
+) -> error::Result<Vec<(PathBuf, Status)>>
+where
+    R::Err: 'static + std::error::Error + Send + Sync,
+{
+    let mut statuses = Vec::with_capacity(entries.0.len());
+    for (target, profile) in entries.iter() {
+        let status = match Fingerprint::of(target)? {
+            None => Status::Missing,
+            Some(current) => match fingerprints.get(target) {
+                Some(recorded) if !current.same_contents(recorded) => Status::ModifiedInTarget,
+                Some(recorded) => {
+                    if expected_hash(renderer, target, profile)?.as_ref() == Some(&recorded.hash) {
+                        Status::Unchanged
+                    } else {
+                        Status::ModifiedInSource
+                    }
+                }
+                // Nothing has been recorded, compares with its source instead.
+                None => {
+                    if expected_hash(renderer, target, profile)?.as_ref() == Some(&current.hash) {
+                        Status::Unchanged
+                    } else {
+                        Status::ModifiedInTarget
+                    }
+                }
+            },
+        };
+        statuses.push((target.to_owned(), status));
+    }
+    Ok(statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        apply,
+        utils::{apply_options, compile_profile, EmptyRenderer},
+        ConflictPolicy,
+    };
+
+    #[test]
+    fn target_status() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        create_tree!(source.path(), {
+            path: {
+                to: {
+                    source: {
+                        file1,
+                        file2,
+                        file3,
+                        file4,
+                    },
+                },
+            },
+        });
+        let entries = compile_profile(
+            "path/to/target: path/to/source",
+            source.path(),
+            target.path(),
+        );
+        let options = apply_options(ConflictPolicy::Error, "".as_ref());
+        let applied = apply(&mut EmptyRenderer, &entries, &options).unwrap();
+        assert_eq!(applied.fingerprints.len(), 4);
+
+        std::fs::write(target.path().join("path/to/target/file2"), "target").unwrap();
+        std::fs::write(source.path().join("path/to/source/file3"), "source").unwrap();
+        std::fs::remove_file(target.path().join("path/to/target/file4")).unwrap();
+        let statuses = status(&mut EmptyRenderer, &entries, &applied.fingerprints).unwrap();
+        let expected = [
+            ("file1", Status::Unchanged),
+            ("file2", Status::ModifiedInTarget),
+            ("file3", Status::ModifiedInSource),
+            ("file4", Status::Missing),
+        ]
+        .into_iter()
+        .map(|(path, status)| (target.path().join("path/to/target").join(path), status))
+        .collect::<Vec<_>>();
+        assert_eq!(statuses, expected);
+    }
+}
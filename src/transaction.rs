@@ -0,0 +1,328 @@
+use crate::error;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+use thisctx::{IntoError, WithContext};
+use tracing::warn;
+
+const STAGED_SUFFIX: &str = "dbot-staged";
+const REMOVED_SUFFIX: &str = "dbot-removed";
+
+#[derive(Debug)]
+enum Step {
+    CreateDir(PathBuf),
+    Create(PathBuf),
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Remove {
+        path: PathBuf,
+        moved_to: PathBuf,
+    },
+    #[cfg(unix)]
+    SetMode {
+        path: PathBuf,
+        mode: u32,
+    },
+}
+
+/// Records every change made to the filesystem so that they can be undone.
+///
+/// New files are written to a staging file and then atomically renamed to
+/// their final paths, and removed files are only moved aside until the
+/// transaction is committed. Dropping a transaction without committing or
+/// rolling back leaves all changes (and removed files) in place.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    steps: Vec<Step>,
+}
+
+/// Returns `path` if it doesn't exist, otherwise appends the first `.<n>`
+/// suffix which makes it a new path.
+pub(crate) fn unused_path(path: PathBuf) -> PathBuf {
+    let mut unused = path.clone();
+    let mut i = 0;
+    while unused.symlink_metadata().is_ok() {
+        i += 1;
+        let mut name = path.clone().into_os_string();
+        name.push(format!(".{i}"));
+        unused = PathBuf::from(name);
+    }
+    unused
+}
+
+/// Returns a hidden sibling of `path` which doesn't exist yet.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".");
+    name.push(suffix);
+    unused_path(path.with_file_name(name))
+}
+
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+impl Transaction {
+    /// Runs `f` in a new transaction, commits it on success and rolls it back
+    /// on failure. The error of `f` is returned even if rolling back fails.
+    pub fn run<T, E>(f: impl FnOnce(&mut Transaction) -> Result<T, E>) -> Result<T, E>
+    where
+        E: From<error::Error>,
+    {
+        let mut transaction = Transaction::default();
+        match f(&mut transaction) {
+            Ok(t) => {
+                transaction.commit()?;
+                Ok(t)
+            }
+            Err(e) => {
+                // The original error matters more, failed steps have been
+                // logged by `rollback`.
+                if let Err(rollback) = transaction.rollback() {
+                    warn!("Failed to roll back the transaction: {rollback}");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    pub fn create_dir_all(&mut self, dir: &Path) -> error::Result<()> {
+        let missing = dir
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && dir.symlink_metadata().is_err())
+            .collect::<Vec<_>>();
+        for dir in missing.into_iter().rev() {
+            std::fs::create_dir(dir).context(error::IoFailed(dir))?;
+            self.steps.push(Step::CreateDir(dir.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Creates `target` by calling `f` with a staging path and renaming it.
+    fn create_staged(
+        &mut self,
+        target: &Path,
+        f: impl FnOnce(&Path) -> std::io::Result<()>,
+    ) -> error::Result<()> {
+        if let Some(dir) = target.parent() {
+            self.create_dir_all(dir)?;
+        }
+        let staged = sibling_path(target, STAGED_SUFFIX);
+        if let Err(e) = f(&staged).and_then(|_| std::fs::rename(&staged, target)) {
+            remove_path(&staged).ok();
+            return Err(e).context(error::IoFailed(target));
+        }
+        self.steps.push(Step::Create(target.to_owned()));
+        Ok(())
+    }
+
+    pub fn write(&mut self, target: &Path, contents: impl AsRef<[u8]>) -> error::Result<()> {
+        self.create_staged(target, |staged| std::fs::write(staged, contents))
+    }
+
+    pub fn copy(&mut self, source: &Path, target: &Path) -> error::Result<()> {
+        std::fs::metadata(source).context(error::IoFailed(source))?;
+        self.create_staged(target, |staged| std::fs::copy(source, staged).map(|_| ()))
+    }
+
+    pub fn symlink(&mut self, original: &Path, link: &Path) -> error::Result<()> {
+        #[cfg(unix)]
+        {
+            self.create_staged(link, |staged| std::os::unix::fs::symlink(original, staged))
+        }
+        #[cfg(not(unix))]
+        {
+            unreachable!("symlinks are only supported on unix systems")
+        }
+    }
+
+    /// Moves `from` to `to`, which must not exist.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> error::Result<()> {
+        if to.symlink_metadata().is_ok() {
+            return error::DestinationExists(from, to).fail();
+        }
+        if let Some(dir) = to.parent() {
+            self.create_dir_all(dir)?;
+        }
+        std::fs::rename(from, to).context(error::IoFailed(from))?;
+        self.steps.push(Step::Rename {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        });
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub fn set_mode(&mut self, path: &Path, mode: u32) -> error::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let metadata = path.metadata().context(error::IoFailed(path))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .context(error::IoFailed(path))?;
+        self.steps.push(Step::SetMode {
+            path: path.to_owned(),
+            mode: metadata.permissions().mode(),
+        });
+        Ok(())
+    }
+
+    /// Moves `path` aside, it is deleted only after committing.
+    pub fn remove(&mut self, path: &Path) -> error::Result<()> {
+        let moved_to = sibling_path(path, REMOVED_SUFFIX);
+        std::fs::rename(path, &moved_to).context(error::IoFailed(path))?;
+        self.steps.push(Step::Remove {
+            path: path.to_owned(),
+            moved_to,
+        });
+        Ok(())
+    }
+
+    /// Deletes all removed files and finishes the transaction.
+    pub fn commit(self) -> error::Result<()> {
+        for step in self.steps {
+            if let Step::Remove { moved_to, .. } = step {
+                remove_path(&moved_to).context(error::IoFailed(&moved_to))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Undoes all recorded steps in reverse order. Every step is tried even if
+    /// some of them fail, and the first error is returned.
+    pub fn rollback(self) -> error::Result<()> {
+        let mut result = Ok(());
+        for step in self.steps.into_iter().rev() {
+            let (path, res) = match step {
+                Step::CreateDir(dir) => {
+                    let res = std::fs::remove_dir(&dir);
+                    (dir, res)
+                }
+                Step::Create(path) => {
+                    let res = remove_path(&path);
+                    (path, res)
+                }
+                Step::Rename { from, to } => {
+                    let res = std::fs::rename(&to, &from);
+                    (to, res)
+                }
+                Step::Remove { path, moved_to } => {
+                    let res = std::fs::rename(&moved_to, &path);
+                    (moved_to, res)
+                }
+                #[cfg(unix)]
+                Step::SetMode { path, mode } => {
+                    use std::os::unix::fs::PermissionsExt;
+
+                    let res =
+                        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode));
+                    (path, res)
+                }
+            };
+            if let Err(e) = res {
+                warn!("Failed to roll back '{}': {}", path.display(), e);
+                if result.is_ok() {
+                    result = Err(e).context(error::IoFailed(path));
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commit() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let tmp = tempdir.path();
+        create_tree!(tmp, { removed, renamed, });
+        let mut tx = Transaction::default();
+        tx.write(&tmp.join("path/to/file"), "content").unwrap();
+        tx.remove(&tmp.join("removed")).unwrap();
+        tx.rename(&tmp.join("renamed"), &tmp.join("dir/renamed"))
+            .unwrap();
+        tx.commit().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(tmp.join("path/to/file")).unwrap(),
+            "content"
+        );
+        assert!(tmp.join("dir/renamed").exists());
+        let mut entries = std::fs::read_dir(tmp)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, ["dir", "path"]);
+    }
+
+    #[test]
+    fn rename_over_existing() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let tmp = tempdir.path();
+        create_tree!(tmp, { renamed, existing, });
+        std::fs::write(tmp.join("existing"), "existing").unwrap();
+        let mut tx = Transaction::default();
+        assert!(matches!(
+            tx.rename(&tmp.join("renamed"), &tmp.join("existing")),
+            Err(error::Error::DestinationExists(..))
+        ));
+        tx.commit().unwrap();
+        assert!(tmp.join("renamed").exists());
+        assert_eq!(
+            std::fs::read_to_string(tmp.join("existing")).unwrap(),
+            "existing"
+        );
+    }
+
+    #[test]
+    fn run_failed_rollback() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let tmp = tempdir.path();
+        let result = Transaction::run(|tx| -> error::Result<()> {
+            tx.write(&tmp.join("dir/file"), "content")?;
+            // Makes the created directory unremovable.
+            std::fs::write(tmp.join("dir/untracked"), "").unwrap();
+            error::Conflict(tmp.join("dir/file")).fail()
+        });
+        assert!(
+            matches!(&result, Err(error::Error::Conflict(_))),
+            "{result:?}"
+        );
+        assert!(!tmp.join("dir/file").exists());
+    }
+
+    #[test]
+    fn rollback() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let tmp = tempdir.path();
+        create_tree!(tmp, { removed, renamed, });
+        std::fs::write(tmp.join("removed"), "removed").unwrap();
+        let mut tx = Transaction::default();
+        tx.write(&tmp.join("path/to/file"), "content").unwrap();
+        tx.copy(&tmp.join("removed"), &tmp.join("copied")).unwrap();
+        tx.remove(&tmp.join("removed")).unwrap();
+        tx.rename(&tmp.join("renamed"), &tmp.join("dir/renamed"))
+            .unwrap();
+        tx.rollback().unwrap();
+        let mut entries = std::fs::read_dir(tmp)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(entries, ["removed", "renamed"]);
+        assert_eq!(
+            std::fs::read_to_string(tmp.join("removed")).unwrap(),
+            "removed"
+        );
+    }
+}
@@ -1,7 +1,60 @@
+#[cfg(test)]
+pub(crate) use test_utils::*;
+
 #[cfg(test)]
 #[macro_use]
 mod test_utils {
+    use crate::{
+        compile,
+        compile::{CompiledEntries, CompilerOptions},
+        ApplyOptions, ConflictPolicy, Profile, RenderContext, TemplateRenderer,
+    };
     use std::path::Path;
+    use thiserror::Error;
+
+    pub struct EmptyRenderer;
+
+    #[derive(Debug, Error)]
+    #[error("")]
+    pub struct RendererErr;
+
+    impl TemplateRenderer for EmptyRenderer {
+        type Err = RendererErr;
+
+        fn render(&mut self, s: &str, _: &RenderContext) -> Result<String, Self::Err> {
+            Ok(s.to_owned())
+        }
+    }
+
+    pub fn apply_options(conflict: ConflictPolicy, backup_dir: &Path) -> ApplyOptions<'_> {
+        ApplyOptions {
+            conflict,
+            backup_dir,
+            rename_suffix: ".bak",
+        }
+    }
+
+    pub fn compiler_options<'a>(source: &'a Path, target: &'a Path) -> CompilerOptions<'a> {
+        CompilerOptions {
+            source,
+            target,
+            relative_links: false,
+            later_wins: false,
+            excluded: Vec::new(),
+        }
+    }
+
+    /// Compiles a profile written in YAML with default options.
+    pub fn compile_profile(profile: &str, source: &Path, target: &Path) -> CompiledEntries {
+        compile(
+            &compiler_options(source, target),
+            serde_yaml::from_str::<Profile>(profile)
+                .unwrap()
+                .into_entries()
+                .unwrap(),
+        )
+        .unwrap()
+    }
 
     pub fn touch(path: &Path) {
         std::fs::File::create(path).unwrap();
@@ -43,6 +96,3 @@ mod test_utils {
         ($path:expr, {}) => {};
     }
 }
-
-#[cfg(test)]
-pub(crate) use test_utils::*;